@@ -4,8 +4,18 @@ fn main() {
     #[cfg(debug_assertions)]
     init_tracing();
 
-    let client = ErebusClient::start("127.0.0.1:58469").unwrap();
-    client.register("OGffXvbHRXyv3JKaqHzItFFePwsHHfSkbB7k35BD9ls");
+    #[cfg(not(feature = "tls"))]
+    let client = ErebusClient::start("127.0.0.1:58469", "./data/client.profile").unwrap();
+    #[cfg(feature = "tls")]
+    let client = ErebusClient::start_tls(
+        "127.0.0.1:58469",
+        "./data/client.profile",
+        erebus_core::transport::tls::ClientTlsConfig::PinnedCertificate(
+            std::env::var("TLS_CERT_PATH").unwrap().into(),
+        ),
+    )
+    .unwrap();
+    client.register("OGffXvbHRXyv3JKaqHzItFFePwsHHfSkbB7k35BD9ls", "erebus");
     loop {
         std::thread::sleep(std::time::Duration::from_millis(100));
     }
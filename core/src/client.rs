@@ -1,8 +1,17 @@
-use crate::client::command::ClientCommand;
+use crate::client::command::{ClientCommand, CommandRequest};
+use crate::client::context::ErebusClientContext;
+use crate::client::event::ClientEvent;
+use crate::client::profile::ClientProfile;
 use crate::client::state::ClientState;
-use crate::error::ErebusResult;
-use std::sync::mpsc::{Receiver, Sender};
+use crate::error::{ErebusError, ErebusResult};
+#[cfg(feature = "tls")]
+use crate::transport::tls::ClientTlsConfig;
+use std::path::Path;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_stream::wrappers::UnboundedReceiverStream;
 
+#[cfg(feature = "client")]
+mod backoff;
 #[cfg(feature = "client")]
 pub mod command;
 #[cfg(feature = "client")]
@@ -12,29 +21,79 @@ pub mod error;
 pub mod event;
 pub mod message;
 #[cfg(feature = "client")]
+pub mod profile;
+#[cfg(feature = "client")]
+mod proof_of_work;
+#[cfg(feature = "client")]
 mod state;
 
+/// Stream of events emitted by an [`AsyncErebusClient`].
+#[cfg(feature = "client")]
+pub type ClientEventStream = UnboundedReceiverStream<ClientEvent>;
+
+#[cfg(feature = "client")]
+fn create_context(
+    server_address: impl AsRef<str>,
+    profile_path: impl AsRef<Path>,
+) -> ErebusResult<(
+    ClientState,
+    ErebusClientContext,
+    UnboundedSender<CommandRequest>,
+    UnboundedReceiver<ClientEvent>,
+)> {
+    let (command_sender, command_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (event_sender, event_receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    let profile = ClientProfile::load(profile_path)?;
+    let state = ClientState::initialize(profile);
+    let context = ErebusClientContext::new(
+        state.clone(),
+        server_address,
+        command_receiver,
+        event_sender,
+    )?;
+
+    Ok((state, context, command_sender, event_receiver))
+}
+
+/// Blocking client, running its connection on a dedicated thread with its own runtime.
 #[cfg(feature = "client")]
 pub struct ErebusClient {
     pub state: ClientState,
-    command_sender: Sender<command::ClientCommand>,
-    event_receiver: Receiver<event::ClientEvent>,
+    command_sender: UnboundedSender<CommandRequest>,
+    event_receiver: UnboundedReceiver<ClientEvent>,
     thread_handle: Option<std::thread::JoinHandle<()>>,
 }
 
 #[cfg(feature = "client")]
 impl ErebusClient {
-    pub fn start(server_address: impl AsRef<str>) -> ErebusResult<Self> {
-        let (command_sender, command_receiver) = std::sync::mpsc::channel();
-        let (event_sender, event_receiver) = std::sync::mpsc::channel();
-
-        let state = ClientState::initialize();
-        let thread_handle = context::ErebusClientContext::spawn(
-            state.clone(),
-            server_address,
-            command_receiver,
-            event_sender,
-        )?;
+    pub fn start(
+        server_address: impl AsRef<str>,
+        profile_path: impl AsRef<Path>,
+    ) -> ErebusResult<Self> {
+        Self::launch(server_address, profile_path, |context| context)
+    }
+
+    /// Like [`Self::start`], but connects to the server over TLS.
+    #[cfg(feature = "tls")]
+    pub fn start_tls(
+        server_address: impl AsRef<str>,
+        profile_path: impl AsRef<Path>,
+        tls: ClientTlsConfig,
+    ) -> ErebusResult<Self> {
+        Self::launch(server_address, profile_path, |context| {
+            context.with_tls(tls)
+        })
+    }
+
+    fn launch(
+        server_address: impl AsRef<str>,
+        profile_path: impl AsRef<Path>,
+        configure: impl FnOnce(ErebusClientContext) -> ErebusClientContext,
+    ) -> ErebusResult<Self> {
+        let (state, context, command_sender, event_receiver) =
+            create_context(server_address, profile_path)?;
+        let thread_handle = configure(context).spawn();
 
         Ok(Self {
             state,
@@ -44,26 +103,179 @@ impl ErebusClient {
         })
     }
 
-    pub fn poll_events(&self) -> Vec<event::ClientEvent> {
-        self.event_receiver.try_iter().collect()
+    pub fn poll_events(&mut self) -> Vec<ClientEvent> {
+        std::iter::from_fn(|| self.event_receiver.try_recv().ok()).collect()
     }
 
-    pub fn send_command(&self, command: command::ClientCommand) {
-        let _ = self.command_sender.send(command);
+    pub fn send_command(&self, command: ClientCommand) {
+        let _ = self.command_sender.send(CommandRequest::new(command));
     }
 
-    pub fn register(&self, invite_code: impl AsRef<str>) {
+    /// Connects again after [`Self::disconnect`], or skips the current reconnect delay.
+    pub fn connect(&self) {
+        self.send_command(ClientCommand::Connect)
+    }
+
+    /// Closes the connection, the client stays offline until [`Self::connect`].
+    pub fn disconnect(&self) {
+        self.send_command(ClientCommand::Disconnect)
+    }
+
+    pub fn login(&self) {
+        self.send_command(ClientCommand::Login)
+    }
+
+    /// Logs out, the client won't log in again on its own until [`Self::login`].
+    pub fn logout(&self) {
+        self.send_command(ClientCommand::Logout)
+    }
+
+    pub fn register(&self, invite_code: impl AsRef<str>, username: impl AsRef<str>) {
         self.send_command(ClientCommand::Register {
             invite_code: invite_code.as_ref().to_string(),
+            username: username.as_ref().to_string(),
         })
     }
+
+    /// Closes the connection and waits for the client thread to finish.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.send_command(ClientCommand::Shutdown);
+        if let Some(thread_handle) = self.thread_handle.take() {
+            let _ = thread_handle.join();
+        }
+    }
 }
 
 #[cfg(feature = "client")]
 impl Drop for ErebusClient {
     fn drop(&mut self) {
-        if let Some(thread_handle) = self.thread_handle.take() {
-            let _ = thread_handle.join();
+        self.stop();
+    }
+}
+
+/// Client for callers which already run inside a tokio runtime.
+#[cfg(feature = "client")]
+pub struct AsyncErebusClient {
+    pub state: ClientState,
+    command_sender: UnboundedSender<CommandRequest>,
+    events: ClientEventStream,
+    task_handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+#[cfg(feature = "client")]
+impl AsyncErebusClient {
+    /// Spawns the client context on the current tokio runtime.
+    pub fn start(
+        server_address: impl AsRef<str>,
+        profile_path: impl AsRef<Path>,
+    ) -> ErebusResult<Self> {
+        Self::launch(server_address, profile_path, |context| context)
+    }
+
+    /// Like [`Self::start`], but connects to the server over TLS.
+    #[cfg(feature = "tls")]
+    pub fn start_tls(
+        server_address: impl AsRef<str>,
+        profile_path: impl AsRef<Path>,
+        tls: ClientTlsConfig,
+    ) -> ErebusResult<Self> {
+        Self::launch(server_address, profile_path, |context| {
+            context.with_tls(tls)
+        })
+    }
+
+    fn launch(
+        server_address: impl AsRef<str>,
+        profile_path: impl AsRef<Path>,
+        configure: impl FnOnce(ErebusClientContext) -> ErebusClientContext,
+    ) -> ErebusResult<Self> {
+        let (state, context, command_sender, event_receiver) =
+            create_context(server_address, profile_path)?;
+        let context = configure(context);
+        let task_handle = tokio::spawn(async move { context.run_with_reconnect().await });
+
+        Ok(Self {
+            state,
+            command_sender,
+            events: UnboundedReceiverStream::new(event_receiver),
+            task_handle: Some(task_handle),
+        })
+    }
+
+    /// The events emitted by the client, to be consumed with `StreamExt`.
+    pub fn events(&mut self) -> &mut ClientEventStream {
+        &mut self.events
+    }
+
+    pub async fn next_event(&mut self) -> Option<ClientEvent> {
+        self.events.as_mut().recv().await
+    }
+
+    /// Resolves once the command was sent to the server, or failed before that.
+    pub async fn send_command(&self, command: ClientCommand) -> ErebusResult<()> {
+        let (request, response) = CommandRequest::with_responder(command);
+        self.command_sender
+            .send(request)
+            .map_err(|_| ErebusError::ContextDisconnected)?;
+        response
+            .await
+            .map_err(|_| ErebusError::ContextDisconnected)?
+    }
+
+    pub async fn register(
+        &self,
+        invite_code: impl AsRef<str>,
+        username: impl AsRef<str>,
+    ) -> ErebusResult<()> {
+        self.send_command(ClientCommand::Register {
+            invite_code: invite_code.as_ref().to_string(),
+            username: username.as_ref().to_string(),
+        })
+        .await
+    }
+
+    /// Connects again after [`Self::disconnect`], or skips the current reconnect delay.
+    pub async fn connect(&self) -> ErebusResult<()> {
+        self.send_command(ClientCommand::Connect).await
+    }
+
+    /// Closes the connection, the client stays offline until [`Self::connect`].
+    pub async fn disconnect(&self) -> ErebusResult<()> {
+        self.send_command(ClientCommand::Disconnect).await
+    }
+
+    pub async fn login(&self) -> ErebusResult<()> {
+        self.send_command(ClientCommand::Login).await
+    }
+
+    /// Logs out, the client won't log in again on its own until [`Self::login`].
+    pub async fn logout(&self) -> ErebusResult<()> {
+        self.send_command(ClientCommand::Logout).await
+    }
+
+    /// Closes the connection and waits for the client task to finish.
+    pub async fn shutdown(mut self) {
+        let _ = self
+            .command_sender
+            .send(CommandRequest::new(ClientCommand::Shutdown));
+        if let Some(task_handle) = self.task_handle.take() {
+            let _ = task_handle.await;
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl Drop for AsyncErebusClient {
+    /// Asks the client task to shut down without waiting for it, use [`Self::shutdown`] to wait.
+    fn drop(&mut self) {
+        if self.task_handle.is_some() {
+            let _ = self
+                .command_sender
+                .send(CommandRequest::new(ClientCommand::Shutdown));
         }
     }
 }
@@ -0,0 +1,90 @@
+use rand_core::{OsRng, RngCore};
+use std::time::Duration;
+
+/// Exponential reconnect backoff with random jitter.
+#[derive(Default)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    const BASE_DELAY: Duration = Duration::from_millis(500);
+    const MAX_DELAY: Duration = Duration::from_secs(30);
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Returns the delay before the next attempt, between 1x and 1.5x of the exponential step.
+    pub fn next_delay(&mut self) -> Duration {
+        self.next_delay_with(OsRng.next_u64())
+    }
+
+    /// [`Self::next_delay`] with the jitter taken from `random`.
+    fn next_delay_with(&mut self, random: u64) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempt.min(16));
+        let delay = Self::BASE_DELAY.saturating_mul(factor).min(Self::MAX_DELAY);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let jitter_ms = random % (delay.as_millis() as u64 / 2 + 1);
+        delay + Duration::from_millis(jitter_ms)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_per_attempt() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<_> = (0..4).map(|_| backoff.next_delay_with(0)).collect();
+
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000].map(Duration::from_millis).to_vec()
+        );
+        assert_eq!(backoff.attempt(), 4);
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let mut backoff = Backoff::default();
+        for _ in 0..100 {
+            assert!(backoff.next_delay_with(0) <= Backoff::MAX_DELAY);
+        }
+
+        assert_eq!(backoff.next_delay_with(0), Backoff::MAX_DELAY);
+        // The largest jitter is half the step.
+        assert_eq!(
+            backoff.next_delay_with(15_000),
+            Backoff::MAX_DELAY + Backoff::MAX_DELAY / 2
+        );
+    }
+
+    #[test]
+    fn jitter_adds_at_most_half_the_step() {
+        for attempt in 0..20 {
+            let step = Backoff { attempt }.next_delay_with(0);
+            for random in [1, 250, 7919, u64::MAX / 3, u64::MAX] {
+                let delay = Backoff { attempt }.next_delay_with(random);
+                assert!(delay >= step && delay <= step + step / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::default();
+        backoff.next_delay_with(0);
+        backoff.next_delay_with(0);
+        backoff.reset();
+
+        assert_eq!(backoff.attempt(), 0);
+        assert_eq!(backoff.next_delay_with(0), Backoff::BASE_DELAY);
+    }
+}
@@ -1,3 +1,67 @@
+use crate::error::ErebusResult;
+use tokio::sync::oneshot;
+
+#[derive(Clone)]
 pub enum ClientCommand {
-    Register { invite_code: String },
+    /// Connect again after an explicit disconnect, or retry right away while reconnecting.
+    Connect,
+    /// Close the connection and stay offline until [`ClientCommand::Connect`].
+    Disconnect,
+    /// Log in with the stored credentials, re-enabling automatic login after a logout.
+    Login,
+    /// End the session on the server and stop logging in automatically.
+    Logout,
+    /// Close the connection and stop the client context for good.
+    Shutdown,
+    Register {
+        invite_code: String,
+        username: String,
+    },
+}
+
+/// Why the context left a connected session on request.
+pub(crate) enum SessionEnd {
+    Disconnect,
+    Shutdown,
+}
+
+impl ClientCommand {
+    pub(crate) fn session_end(&self) -> Option<SessionEnd> {
+        match self {
+            Self::Disconnect => Some(SessionEnd::Disconnect),
+            Self::Shutdown => Some(SessionEnd::Shutdown),
+            _ => None,
+        }
+    }
+}
+
+/// A command on its way to the context, optionally with someone awaiting its result.
+pub(crate) struct CommandRequest {
+    pub command: ClientCommand,
+    pub responder: Option<oneshot::Sender<ErebusResult<()>>>,
+}
+
+impl CommandRequest {
+    pub fn new(command: ClientCommand) -> Self {
+        Self {
+            command,
+            responder: None,
+        }
+    }
+
+    pub fn with_responder(command: ClientCommand) -> (Self, oneshot::Receiver<ErebusResult<()>>) {
+        let (responder, receiver) = oneshot::channel();
+        let request = Self {
+            command,
+            responder: Some(responder),
+        };
+        (request, receiver)
+    }
+
+    /// Reports success to whoever awaits the command.
+    pub fn complete(self) {
+        if let Some(responder) = self.responder {
+            let _ = responder.send(Ok(()));
+        }
+    }
 }
@@ -1,108 +1,489 @@
-use crate::client::command::ClientCommand;
+use crate::client::backoff::Backoff;
+use crate::client::command::{ClientCommand, CommandRequest, SessionEnd};
 use crate::client::error::ErebusClientError;
 use crate::client::event::ClientEvent;
 use crate::client::message::ClientMessage;
+use crate::client::profile::Credentials;
+use crate::client::proof_of_work::ProofOfWorkTask;
 use crate::client::state::ClientState;
-use crate::crypto::registration_challenge::RegistrationChallengeWithCode;
+use crate::client::state::authentication::AuthenticationState;
+use crate::client::state::connection::ConnectionStatus;
+use crate::crypto::private_key::PrivateKey;
+use crate::crypto::proof_of_work::WorkChallenge;
+use crate::crypto::registration_challenge::{RegistrationChallenge, RegistrationChallengeWithCode};
 use crate::error::{ErebusError, ErebusResult};
-use crate::message::{MessageRecv, MessageSend};
+use crate::message::{MessageRecv, MessageSend, PROTOCOL_VERSION};
 use crate::server::message::ServerMessage;
-use std::sync::mpsc::{Receiver, Sender, TryRecvError};
-use tokio::net::tcp::WriteHalf;
+use crate::server::message::error::ErebusServerError;
+#[cfg(feature = "tls")]
+use crate::transport::tls::ClientTlsConfig;
+use crate::transport::{TransportReader, TransportWriter};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+/// How often the client pings the server.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// How long the server may stay silent before the connection is considered dead.
+const DEAD_PEER_TIMEOUT: Duration = Duration::from_secs(45);
+/// How long connecting and the identity handshake may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct ErebusClientContext {
     state: ClientState,
     server_address: String,
-    command_receiver: Receiver<ClientCommand>,
-    event_sender: Sender<ClientEvent>,
+    command_receiver: tokio::sync::Mutex<UnboundedReceiver<CommandRequest>>,
+    event_sender: UnboundedSender<ClientEvent>,
+    epoch: Instant,
+    /// Commands issued while offline, replayed once the connection is back.
+    outbox: Mutex<VecDeque<CommandRequest>>,
+    /// Whether to log in with the stored credentials after connecting, cleared by a logout.
+    auto_login: AtomicBool,
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTlsConfig>,
 }
 
 impl ErebusClientContext {
-    pub fn spawn(
-        state: ClientState,
-        server_address: impl AsRef<str>,
-        command_receiver: Receiver<ClientCommand>,
-        event_sender: Sender<ClientEvent>,
-    ) -> ErebusResult<std::thread::JoinHandle<()>> {
-        let context = Self::new(state, server_address, command_receiver, event_sender)?;
-        let handle = std::thread::spawn(move || context.run());
-        Ok(handle)
+    pub fn spawn(self) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || self.run())
     }
 
     pub fn new(
         state: ClientState,
         server_address: impl AsRef<str>,
-        command_receiver: Receiver<ClientCommand>,
-        event_sender: Sender<ClientEvent>,
+        command_receiver: UnboundedReceiver<CommandRequest>,
+        event_sender: UnboundedSender<ClientEvent>,
     ) -> ErebusResult<Self> {
         Ok(Self {
             state,
             server_address: server_address.as_ref().to_string(),
-            command_receiver,
+            command_receiver: tokio::sync::Mutex::new(command_receiver),
             event_sender,
+            epoch: Instant::now(),
+            outbox: Mutex::new(VecDeque::new()),
+            auto_login: AtomicBool::new(true),
+            #[cfg(feature = "tls")]
+            tls: None,
         })
     }
 
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
     pub fn run(self) {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap();
 
-        rt.block_on(async {
-            let result = self.run_async().await;
-            if let Err(e) = result {
-                self.send_event(ClientEvent::Error(e));
+        rt.block_on(self.run_with_reconnect());
+    }
+
+    pub async fn run_with_reconnect(&self) {
+        let mut backoff = Backoff::default();
+        let mut has_connected = false;
+
+        loop {
+            let mut connected = false;
+            self.set_status(ConnectionStatus::Connecting);
+            let result = self.run_async(has_connected, &mut connected).await;
+            has_connected |= connected;
+
+            self.state.write_auth(|auth| auth.reset());
+            self.set_status(ConnectionStatus::Disconnected);
+
+            let delay = match result {
+                Ok(SessionEnd::Shutdown) => break,
+                Ok(SessionEnd::Disconnect) => {
+                    info!("Disconnected from {}", self.server_address);
+                    self.send_event(ClientEvent::Disconnected(None));
+                    backoff.reset();
+                    None
+                }
+                Err(error) if !Self::is_recoverable(&error) => {
+                    self.send_event(ClientEvent::Error(error));
+                    return;
+                }
+                Err(error) => {
+                    if connected {
+                        backoff.reset();
+                        info!("Disconnected from {}: {}", self.server_address, error);
+                        self.send_event(ClientEvent::Disconnected(Some(error)));
+                    } else {
+                        warn!("Failed to connect to {}: {}", self.server_address, error);
+                    }
+
+                    let delay = backoff.next_delay();
+                    self.send_event(ClientEvent::Reconnecting {
+                        attempt: backoff.attempt(),
+                        delay,
+                    });
+                    Some(delay)
+                }
+            };
+
+            if !self.wait_offline(delay).await {
+                break;
             }
-        });
+        }
+
+        self.set_status(ConnectionStatus::ShuttingDown);
+        info!("Client for {} shut down", self.server_address);
+        self.send_event(ClientEvent::Shutdown);
+    }
+
+    /// Errors after which reconnecting is pointless or unsafe.
+    fn is_recoverable(error: &ErebusError) -> bool {
+        #[cfg(feature = "tls")]
+        if matches!(error, ErebusError::Tls(_) | ErebusError::TlsCertificate(_)) {
+            return false;
+        }
+
+        !matches!(
+            error,
+            ErebusError::ContextDisconnected
+                | ErebusError::InvalidServerName
+                | ErebusError::Client(
+                    ErebusClientError::ServerIdentityMismatch { .. }
+                        | ErebusClientError::ServerIdentityUnverified
+                )
+        )
+    }
+
+    /// Errors which mean the connection itself is gone.
+    fn is_connection_error(error: &ErebusError) -> bool {
+        matches!(error, ErebusError::Io(_) | ErebusError::ConnectionTimedOut)
     }
 
-    async fn run_async(&self) -> ErebusResult<()> {
-        let mut stream = TcpStream::connect(self.server_address.clone()).await?;
-        let (mut reader, mut writer) = stream.split();
-        self.send_event(ClientEvent::Connected);
+    /// Waits out a reconnect delay, or until a connect command if there is none, moving
+    /// commands issued in the meantime into the outbox. Returns false once the context should
+    /// stop instead of reconnecting.
+    async fn wait_offline(&self, mut delay: Option<Duration>) -> bool {
+        let deadline = delay.map(|delay| tokio::time::Instant::now() + delay);
+        let mut command_receiver = self.command_receiver.lock().await;
 
         loop {
+            let sleep =
+                tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now));
             tokio::select! {
-                command_result = async {
-                    match self.command_receiver.try_recv() {
-                        Ok(command) => Ok(Some(command)),
-                        Err(TryRecvError::Empty) => {
-                            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-                            Ok(None)
-                        },
-                        Err(TryRecvError::Disconnected) => Err(ErebusError::ContextDisconnected),
+                _ = sleep, if delay.is_some() => return true,
+                request = command_receiver.recv() => {
+                    let Some(request) = request else {
+                        return false;
+                    };
+                    match request.command {
+                        ClientCommand::Connect => {
+                            request.complete();
+                            return true;
+                        }
+                        ClientCommand::Disconnect => {
+                            delay = None;
+                            request.complete();
+                        }
+                        ClientCommand::Shutdown => {
+                            request.complete();
+                            return false;
+                        }
+                        _ => self.outbox.lock().unwrap().push_back(request),
                     }
-                } => {
-                    if let Some(command) = command_result? {
-                        let result = self.handle_command(&mut writer, command).await;
-                        if let Err(e) = result {
-                            self.send_event(ClientEvent::Error(e));
+                }
+            }
+        }
+    }
+
+    async fn run_async(&self, reconnect: bool, connected: &mut bool) -> ErebusResult<SessionEnd> {
+        let (reader, mut writer) = tokio::time::timeout(CONNECT_TIMEOUT, async {
+            let (mut reader, mut writer) = self.connect().await?;
+            self.verify_server_identity(&mut reader, &mut writer)
+                .await?;
+            Ok::<_, ErebusError>((reader, writer))
+        })
+        .await
+        .map_err(|_| ErebusError::ConnectionTimedOut)??;
+
+        *connected = true;
+        self.set_status(ConnectionStatus::Connected);
+        if reconnect {
+            self.send_event(ClientEvent::Reconnected);
+        } else {
+            self.send_event(ClientEvent::Connected);
+        }
+
+        let (message_sender, message_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let reader_task = tokio::spawn(Self::read_messages(reader, message_sender));
+        let result = self.run_session(&mut writer, message_receiver).await;
+        reader_task.abort();
+        result
+    }
+
+    async fn run_session(
+        &self,
+        writer: &mut TransportWriter,
+        message_receiver: UnboundedReceiver<ErebusResult<ServerMessage>>,
+    ) -> ErebusResult<SessionEnd> {
+        self.resume_session(writer).await?;
+        self.flush_outbox(writer).await?;
+        self.event_loop(writer, message_receiver).await
+    }
+
+    /// Reads server messages on a separate task, so that other branches in the event loop
+    /// can never cancel a partially read message.
+    async fn read_messages(
+        mut tcp_reader: TransportReader,
+        message_sender: UnboundedSender<ErebusResult<ServerMessage>>,
+    ) {
+        loop {
+            let result = ServerMessage::recv(&mut tcp_reader).await;
+            let failed = result.is_err();
+            if message_sender.send(result).is_err() || failed {
+                break;
+            }
+        }
+    }
+
+    async fn event_loop(
+        &self,
+        writer: &mut TransportWriter,
+        mut message_receiver: UnboundedReceiver<ErebusResult<ServerMessage>>,
+    ) -> ErebusResult<SessionEnd> {
+        let mut heartbeat = tokio::time::interval_at(
+            tokio::time::Instant::now() + HEARTBEAT_INTERVAL,
+            HEARTBEAT_INTERVAL,
+        );
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_seen = Instant::now();
+        let mut command_receiver = self.command_receiver.lock().await;
+        let mut proof_of_work = None;
+
+        loop {
+            tokio::select! {
+                request = command_receiver.recv() => {
+                    let Some(request) = request else {
+                        self.close(writer).await;
+                        return Ok(SessionEnd::Shutdown);
+                    };
+                    if let Some(session_end) = request.command.session_end() {
+                        if let SessionEnd::Shutdown = session_end {
+                            self.set_status(ConnectionStatus::ShuttingDown);
                         }
+                        self.close(writer).await;
+                        request.complete();
+                        return Ok(session_end);
                     }
+                    self.execute_command(writer, request).await?;
                 }
 
-                message_result = ServerMessage::recv(&mut reader) => {
-                    let message = message_result?;
-                    self.handle_message(&mut writer, message).await?;
+                message_result = message_receiver.recv() => {
+                    let message = message_result.ok_or(ErebusError::ContextDisconnected)??;
+                    last_seen = Instant::now();
+                    if let Err(e) = self.handle_message(writer, message, &mut proof_of_work).await {
+                        if Self::is_connection_error(&e) {
+                            return Err(e);
+                        }
+                        self.send_event(ClientEvent::Error(e));
+                    }
+                }
+
+                counter = Self::proof_of_work_solved(&mut proof_of_work) => {
+                    proof_of_work = None;
+                    if let Err(e) = self.handle_proof_of_work_solved(writer, counter).await {
+                        if Self::is_connection_error(&e) {
+                            return Err(e);
+                        }
+                        self.send_event(ClientEvent::Error(e));
+                    }
                 }
+
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > DEAD_PEER_TIMEOUT {
+                        return Err(ErebusError::ConnectionTimedOut);
+                    }
+                    ClientMessage::Ping(self.heartbeat_nonce()).send(writer).await?;
+                }
+            }
+        }
+    }
+
+    /// Tells the server the client is leaving and closes the connection. Errors are ignored,
+    /// the connection is going away either way.
+    async fn close(&self, writer: &mut TransportWriter) {
+        let _ = ClientMessage::Disconnect.send(writer).await;
+        let _ = writer.shutdown().await;
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        self.state
+            .write_connection(|connection| connection.set_status(status));
+    }
+
+    /// Milliseconds since the context was created, echoed back by the peer in a pong.
+    fn heartbeat_nonce(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    async fn connect(&self) -> ErebusResult<(TransportReader, TransportWriter)> {
+        let stream = TcpStream::connect(&self.server_address).await?;
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let stream = tls.connect(&self.server_address, stream).await?;
+            return Ok(crate::transport::split(stream));
+        }
+
+        Ok(crate::transport::split(stream))
+    }
+
+    /// Checks the identity key the server presents against the one pinned in the profile
+    /// (pinning it on first use) and makes the server prove it holds the private key.
+    async fn verify_server_identity(
+        &self,
+        tcp_reader: &mut TransportReader,
+        tcp_writer: &mut TransportWriter,
+    ) -> ErebusResult<()> {
+        let ServerMessage::Identity {
+            identity,
+            ephemeral: server_ephemeral,
+        } = ServerMessage::recv(tcp_reader).await?
+        else {
+            return Err(ErebusClientError::HandshakeFailed("expected server identity").into());
+        };
+
+        let pinned = self
+            .state
+            .read_profile(|profile| profile.pinned_identity(&self.server_address).cloned());
+        if let Some(pinned) = &pinned
+            && *pinned != identity
+        {
+            return Err(ErebusClientError::ServerIdentityMismatch {
+                expected: pinned.fingerprint(),
+                received: identity.fingerprint(),
             }
+            .into());
+        }
+
+        let ephemeral = PrivateKey::generate();
+        ClientMessage::IdentityChallenge {
+            protocol_version: PROTOCOL_VERSION,
+            ephemeral: ephemeral.public_key(),
         }
+        .send(tcp_writer)
+        .await?;
+
+        let ServerMessage::IdentityProof(proof) = ServerMessage::recv(tcp_reader).await? else {
+            return Err(ErebusClientError::HandshakeFailed("expected identity proof").into());
+        };
+        if !proof.verify(&ephemeral, &identity, &server_ephemeral, PROTOCOL_VERSION) {
+            return Err(ErebusClientError::ServerIdentityUnverified.into());
+        }
+
+        if pinned.is_none() {
+            info!(
+                "Pinned identity {} for server {}",
+                identity.fingerprint(),
+                self.server_address
+            );
+            let fingerprint = identity.fingerprint();
+            self.state.write_profile(|profile| {
+                profile.pin_identity(&self.server_address, identity);
+                profile.save()
+            })?;
+            self.send_event(ClientEvent::ServerIdentityPinned { fingerprint });
+        }
+
+        Ok(())
+    }
+
+    /// Logs back in with the stored credentials, if there are any for this server.
+    async fn resume_session(&self, tcp_writer: &mut TransportWriter) -> ErebusResult<()> {
+        if !self.auto_login.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let Some(credentials) = self.credentials() else {
+            return Ok(());
+        };
+
+        debug!("Logging in as {}", credentials.username);
+        self.state
+            .write_auth(|auth| *auth = AuthenticationState::LoginPending);
+        ClientMessage::LoginRequest(credentials.private_key.public_key())
+            .send(tcp_writer)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn flush_outbox(&self, tcp_writer: &mut TransportWriter) -> ErebusResult<()> {
+        loop {
+            let Some(request) = self.outbox.lock().unwrap().pop_front() else {
+                return Ok(());
+            };
+            self.execute_command(tcp_writer, request).await?;
+        }
+    }
+
+    fn credentials(&self) -> Option<Credentials> {
+        self.state
+            .read_profile(|profile| profile.credentials(&self.server_address).cloned())
     }
 
     fn send_event(&self, event: ClientEvent) {
         let _ = self.event_sender.send(event);
     }
 
+    /// Runs a command, putting it back into the outbox if the connection dropped meanwhile.
+    /// Other results go to whoever awaits the command, or out as an event otherwise.
+    async fn execute_command(
+        &self,
+        tcp_writer: &mut TransportWriter,
+        request: CommandRequest,
+    ) -> ErebusResult<()> {
+        let result = self
+            .handle_command(tcp_writer, request.command.clone())
+            .await;
+
+        if let Err(e) = &result
+            && Self::is_connection_error(e)
+        {
+            self.outbox.lock().unwrap().push_front(request);
+            return result;
+        }
+
+        match (request.responder, result) {
+            (Some(responder), result) => {
+                let _ = responder.send(result);
+            }
+            (None, Err(e)) => self.send_event(ClientEvent::Error(e)),
+            (None, Ok(())) => {}
+        }
+
+        Ok(())
+    }
+
     async fn handle_command(
         &self,
-        tcp_writer: &mut WriteHalf<'_>,
+        tcp_writer: &mut TransportWriter,
         command: ClientCommand,
     ) -> ErebusResult<()> {
         match command {
-            ClientCommand::Register { invite_code } => {
-                self.handle_register(tcp_writer, invite_code).await?
+            // Connecting, disconnecting and shutting down are handled by the event loop
+            ClientCommand::Connect | ClientCommand::Disconnect | ClientCommand::Shutdown => {}
+            ClientCommand::Login => self.handle_login(tcp_writer).await?,
+            ClientCommand::Logout => self.handle_logout(tcp_writer).await?,
+            ClientCommand::Register {
+                invite_code,
+                username,
+            } => {
+                self.handle_register(tcp_writer, invite_code, username)
+                    .await?
             }
         }
 
@@ -111,27 +492,250 @@ impl ErebusClientContext {
 
     async fn handle_message(
         &self,
-        tcp_writer: &mut WriteHalf<'_>,
+        tcp_writer: &mut TransportWriter,
         message: ServerMessage,
+        proof_of_work: &mut Option<ProofOfWorkTask>,
+    ) -> ErebusResult<()> {
+        match message {
+            ServerMessage::Ping(nonce) => ClientMessage::Pong(nonce).send(tcp_writer).await?,
+            ServerMessage::Pong(nonce) => self.handle_pong(nonce),
+            ServerMessage::Error(error) => self.handle_server_error(error),
+            ServerMessage::WorkChallenge(challenge) => {
+                *proof_of_work = Some(self.handle_work_challenge(challenge)?)
+            }
+            ServerMessage::RegisterChallengeSolved(solved_challenge) => {
+                self.handle_register_challenge_solved(tcp_writer, solved_challenge)
+                    .await?
+            }
+            ServerMessage::Registered => self.handle_registered(tcp_writer).await?,
+            ServerMessage::LoginChallenge(challenge) => {
+                self.handle_login_challenge(tcp_writer, challenge).await?
+            }
+            ServerMessage::LoginSuccess { username } => self.handle_login_success(username),
+            ServerMessage::LoggedOut => self.handle_logged_out(),
+            ServerMessage::Kicked => {
+                warn!("Kicked by server {}", self.server_address);
+                self.send_event(ClientEvent::Kicked);
+            }
+            ServerMessage::ShuttingDown => {
+                info!("Server {} is shutting down", self.server_address);
+                self.send_event(ClientEvent::ServerShuttingDown);
+            }
+            ServerMessage::Identity { .. } | ServerMessage::IdentityProof(_) => {
+                return Err(
+                    ErebusClientError::UnexpectedMessage("identity after handshake").into(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Message handling
+impl ErebusClientContext {
+    fn handle_pong(&self, nonce: u64) {
+        let round_trip = self.heartbeat_nonce().saturating_sub(nonce);
+        let latency = Duration::from_millis(round_trip);
+        debug!("Measured round-trip time of {:?}", latency);
+        self.state
+            .write_connection(|connection| connection.set_latency(latency));
+        self.send_event(ClientEvent::LatencyMeasured(latency));
+    }
+
+    /// Reports a server error as the failure of whatever the client was waiting for.
+    fn handle_server_error(&self, error: ErebusServerError) {
+        warn!("Server responded with an error: {error}");
+        let pending = self.state.write_auth(|auth| {
+            if auth.is_authenticated() {
+                None
+            } else {
+                Some(std::mem::take(auth))
+            }
+        });
+
+        let event = match pending {
+            Some(
+                AuthenticationState::RegistrationChallengePending { .. }
+                | AuthenticationState::RegistrationPending { .. },
+            ) => ClientEvent::RegistrationFailed(error),
+            Some(AuthenticationState::LoginPending) => ClientEvent::LoginFailed(error),
+            _ => ClientEvent::ServerError(error),
+        };
+        self.send_event(event);
+    }
+
+    /// Starts solving the proof of work the server demands before a registration challenge.
+    fn handle_work_challenge(&self, challenge: WorkChallenge) -> ErebusResult<ProofOfWorkTask> {
+        if !self.is_registration_challenge_pending() {
+            return Err(ErebusClientError::UnexpectedMessage("work challenge").into());
+        }
+        if challenge.difficulty > WorkChallenge::MAX_DIFFICULTY {
+            self.state.write_auth(|auth| auth.reset());
+            return Err(ErebusClientError::ProofOfWorkTooHard(challenge.difficulty).into());
+        }
+
+        debug!(
+            "Solving proof of work with difficulty {}",
+            challenge.difficulty
+        );
+        self.send_event(ClientEvent::SolvingProofOfWork {
+            difficulty: challenge.difficulty,
+        });
+        Ok(ProofOfWorkTask::spawn(challenge))
+    }
+
+    /// Waits for the proof of work being solved, never resolves if there is none.
+    async fn proof_of_work_solved(proof_of_work: &mut Option<ProofOfWorkTask>) -> Option<u64> {
+        match proof_of_work {
+            Some(task) => task.solved().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn handle_proof_of_work_solved(
+        &self,
+        tcp_writer: &mut TransportWriter,
+        counter: Option<u64>,
     ) -> ErebusResult<()> {
+        // The registration may have failed meanwhile, e.g. because the server gave up on it
+        if !self.is_registration_challenge_pending() {
+            return Ok(());
+        }
+        let Some(counter) = counter else {
+            self.state.write_auth(|auth| auth.reset());
+            return Err(ErebusClientError::ProofOfWorkAborted.into());
+        };
+
+        ClientMessage::ProofOfWork(counter).send(tcp_writer).await?;
+        Ok(())
+    }
+
+    fn is_registration_challenge_pending(&self) -> bool {
+        self.state.read_auth(|auth| {
+            matches!(
+                auth,
+                AuthenticationState::RegistrationChallengePending { .. }
+            )
+        })
+    }
+
+    async fn handle_register_challenge_solved(
+        &self,
+        tcp_writer: &mut TransportWriter,
+        solved_challenge: RegistrationChallenge,
+    ) -> ErebusResult<()> {
+        let AuthenticationState::RegistrationChallengePending {
+            original_challenge,
+            invite_code,
+            username,
+        } = self.state.write_auth(std::mem::take)
+        else {
+            return Err(ErebusClientError::UnexpectedMessage("registration challenge").into());
+        };
+
+        if !original_challenge.verify(&solved_challenge) {
+            return Err(ErebusClientError::RegistrationChallengeFailed.into());
+        }
+        self.send_event(ClientEvent::InviteCodeAccepted);
+
+        let private_key = PrivateKey::generate();
+        ClientMessage::Register {
+            invite_code,
+            username: username.clone(),
+            public_key: private_key.public_key(),
+        }
+        .send(tcp_writer)
+        .await?;
+
+        self.state.write_auth(|auth| {
+            *auth = AuthenticationState::RegistrationPending {
+                username,
+                private_key,
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn handle_registered(&self, tcp_writer: &mut TransportWriter) -> ErebusResult<()> {
+        let AuthenticationState::RegistrationPending {
+            username,
+            private_key,
+        } = self.state.write_auth(std::mem::take)
+        else {
+            return Err(ErebusClientError::UnexpectedMessage("registration confirmation").into());
+        };
+
+        info!("Registered as {username}");
+        self.state.write_profile(|profile| {
+            profile.set_credentials(
+                &self.server_address,
+                Credentials {
+                    username: username.clone(),
+                    private_key,
+                },
+            );
+            profile.save()
+        })?;
+        self.send_event(ClientEvent::Registered { username });
+
+        self.resume_session(tcp_writer).await
+    }
+
+    async fn handle_login_challenge(
+        &self,
+        tcp_writer: &mut TransportWriter,
+        challenge: RegistrationChallenge,
+    ) -> ErebusResult<()> {
+        let credentials = self
+            .credentials()
+            .ok_or(ErebusClientError::MissingCredentials)?;
+        let solved_challenge = challenge.decrypt(&credentials.private_key)?;
+
+        ClientMessage::LoginResponse(solved_challenge)
+            .send(tcp_writer)
+            .await?;
+
         Ok(())
     }
+
+    fn handle_login_success(&self, username: String) {
+        info!("Logged in as {username}");
+        self.state.write_auth(|auth| {
+            *auth = AuthenticationState::Authenticated {
+                username: username.clone(),
+            }
+        });
+        self.set_status(ConnectionStatus::Authenticated);
+        self.send_event(ClientEvent::LoggedIn { username });
+    }
+
+    fn handle_logged_out(&self) {
+        info!("Logged out");
+        self.state.write_auth(|auth| auth.reset());
+        self.set_status(ConnectionStatus::Connected);
+        self.send_event(ClientEvent::LoggedOut);
+    }
 }
 
 // Command handling
 impl ErebusClientContext {
     async fn handle_register(
         &self,
-        tcp_writer: &mut WriteHalf<'_>,
+        tcp_writer: &mut TransportWriter,
         invite_code: String,
+        username: String,
     ) -> ErebusResult<()> {
-        if !self.state.read_auth(|auth| auth.can_register()) {
+        if !self.state.read_auth(|auth| auth.can_register()) || self.credentials().is_some() {
             return Err(ErebusClientError::AlreadyRegistered.into());
         };
 
         let (payload, original_challenge) = RegistrationChallengeWithCode::generate(invite_code)?;
-        self.state
-            .write_auth(|auth| auth.set_authentication_pending(original_challenge));
+        let invite_code = payload.invite_code.clone();
+        self.state.write_auth(|auth| {
+            auth.set_authentication_pending(original_challenge, invite_code, username)
+        });
 
         ClientMessage::RegisterChallenge(payload)
             .send(tcp_writer)
@@ -139,4 +743,22 @@ impl ErebusClientContext {
 
         Ok(())
     }
+
+    async fn handle_login(&self, tcp_writer: &mut TransportWriter) -> ErebusResult<()> {
+        if self.credentials().is_none() {
+            return Err(ErebusClientError::MissingCredentials.into());
+        }
+        if self.state.read_auth(|auth| auth.is_authenticated()) {
+            return Ok(());
+        }
+
+        self.auto_login.store(true, Ordering::Relaxed);
+        self.resume_session(tcp_writer).await
+    }
+
+    async fn handle_logout(&self, tcp_writer: &mut TransportWriter) -> ErebusResult<()> {
+        self.auto_login.store(false, Ordering::Relaxed);
+        ClientMessage::Logout.send(tcp_writer).await?;
+        Ok(())
+    }
 }
@@ -4,4 +4,20 @@ use thiserror::Error;
 pub enum ErebusClientError {
     #[error("Already registered")]
     AlreadyRegistered,
+    #[error("Server failed to solve the registration challenge")]
+    RegistrationChallengeFailed,
+    #[error("Solving the proof of work was aborted")]
+    ProofOfWorkAborted,
+    #[error("Server demanded a proof of work of {0} bits, more than the client solves")]
+    ProofOfWorkTooHard(u8),
+    #[error("No credentials stored for this server")]
+    MissingCredentials,
+    #[error("Unexpected message from server: {0}")]
+    UnexpectedMessage(&'static str),
+    #[error("Handshake failed: {0}")]
+    HandshakeFailed(&'static str),
+    #[error("Server identity mismatch, pinned {expected} but server presented {received}")]
+    ServerIdentityMismatch { expected: String, received: String },
+    #[error("Server failed to prove possession of its identity key")]
+    ServerIdentityUnverified,
 }
@@ -1,6 +1,47 @@
 use crate::error::ErebusError;
+use crate::server::message::error::ErebusServerError;
+use std::time::Duration;
 
 pub enum ClientEvent {
     Connected,
+    /// The connection to the server ended, with the error if it was lost rather than closed.
+    /// Unless it was closed on purpose, the context will try to reconnect.
+    Disconnected(Option<ErebusError>),
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    Reconnected,
+    /// The client context stopped after a shutdown was requested.
+    Shutdown,
+    /// The server is shutting down, the context will reconnect once it is back.
+    ServerShuttingDown,
+    /// An admin closed the connection, the context will try to reconnect.
+    Kicked,
+    /// First connection to this server, its identity key is now pinned in the profile.
+    ServerIdentityPinned {
+        fingerprint: String,
+    },
+    /// Round-trip time measured by a heartbeat.
+    LatencyMeasured(Duration),
+    /// The server demands a proof of work before registering, the context is solving it.
+    SolvingProofOfWork {
+        difficulty: u8,
+    },
+    /// The server knows the invite code, registration continues.
+    InviteCodeAccepted,
+    /// The account was created and its credentials were saved to the profile.
+    Registered {
+        username: String,
+    },
+    RegistrationFailed(ErebusServerError),
+    LoggedIn {
+        username: String,
+    },
+    LoginFailed(ErebusServerError),
+    LoggedOut,
+    /// An error the server reported outside of registration or login.
+    ServerError(ErebusServerError),
+    /// A local error, e.g. a command which could not be executed.
     Error(ErebusError),
 }
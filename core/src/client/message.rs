@@ -1,7 +1,30 @@
-use crate::crypto::registration_challenge::RegistrationChallengeWithCode;
+use crate::crypto::public_key::PublicKey;
+use crate::crypto::registration_challenge::{RegistrationChallenge, RegistrationChallengeWithCode};
 use bincode::{Decode, Encode};
 
 #[derive(Encode, Decode)]
 pub enum ClientMessage {
+    /// Asks the server to prove it holds its identity key, for a handshake with the client's
+    /// key generated for this connection only.
+    IdentityChallenge {
+        protocol_version: u16,
+        ephemeral: PublicKey,
+    },
+    Ping(u64),
+    Pong(u64),
     RegisterChallenge(RegistrationChallengeWithCode),
+    /// Solution to the work challenge the server demanded before a registration challenge.
+    ProofOfWork(u64),
+    /// Sent once the server solved the registration challenge, creating the account.
+    Register {
+        invite_code: PublicKey,
+        username: String,
+        public_key: PublicKey,
+    },
+    /// Sent before the client closes the connection on purpose.
+    Disconnect,
+    LoginRequest(PublicKey),
+    /// The login challenge, decrypted with the user's private key.
+    LoginResponse(RegistrationChallenge),
+    Logout,
 }
@@ -0,0 +1,113 @@
+use crate::crypto::private_key::PrivateKey;
+use crate::crypto::public_key::PublicKey;
+use crate::error::ErebusResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Persistent client-side data, stored next to the client.
+#[derive(Default, Serialize, Deserialize)]
+pub struct ClientProfile {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Server identity keys pinned on first use, by server address.
+    known_servers: HashMap<String, PublicKey>,
+    /// Account credentials, by server address.
+    #[serde(default)]
+    credentials: HashMap<String, Credentials>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub private_key: PrivateKey,
+}
+
+impl ClientProfile {
+    pub fn load(path: impl AsRef<Path>) -> ErebusResult<Self> {
+        let path = path.as_ref();
+        let mut profile = if path.exists() {
+            let bytes = std::fs::read(path)?;
+            rmp_serde::from_slice::<Self>(&bytes)?
+        } else {
+            Self::default()
+        };
+
+        profile.path = path.to_path_buf();
+        Ok(profile)
+    }
+
+    pub fn save(&self) -> ErebusResult<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // The profile holds private keys, so it is only ever readable by the owner. It is
+        // staged and renamed into place, which also tightens a file written with looser
+        // permissions before.
+        let staged_path = self.path.with_extension("tmp");
+        let _ = std::fs::remove_file(&staged_path);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&staged_path)?;
+        file.write_all(&rmp_serde::to_vec_named(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(&staged_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn pinned_identity(&self, server_address: &str) -> Option<&PublicKey> {
+        self.known_servers.get(server_address)
+    }
+
+    pub fn pin_identity(&mut self, server_address: &str, identity: PublicKey) {
+        self.known_servers
+            .insert(server_address.to_string(), identity);
+    }
+
+    pub fn credentials(&self, server_address: &str) -> Option<&Credentials> {
+        self.credentials.get(server_address)
+    }
+
+    pub fn set_credentials(&mut self, server_address: &str, credentials: Credentials) {
+        self.credentials
+            .insert(server_address.to_string(), credentials);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn saved_profile_is_private() {
+        let dir = std::env::temp_dir().join(format!("erebus-profile-{}", std::process::id()));
+        let path = dir.join("profile");
+        let mut profile = ClientProfile::load(&path).unwrap();
+        profile.save().unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        profile.set_credentials(
+            "localhost",
+            Credentials {
+                username: "erebus".to_string(),
+                private_key: PrivateKey::generate(),
+            },
+        );
+        profile.save().unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        let loaded = ClientProfile::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(loaded.credentials("localhost").is_some());
+    }
+}
@@ -0,0 +1,31 @@
+use crate::crypto::proof_of_work::WorkChallenge;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::task::JoinHandle;
+
+/// A proof of work being solved on a blocking thread, so the connection keeps being served
+/// meanwhile. Solving stops when the task is dropped.
+pub struct ProofOfWorkTask {
+    handle: JoinHandle<Option<u64>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ProofOfWorkTask {
+    pub fn spawn(challenge: WorkChallenge) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancelled_clone = cancelled.clone();
+        let handle = tokio::task::spawn_blocking(move || challenge.solve(&cancelled_clone));
+        Self { handle, cancelled }
+    }
+
+    /// Waits for the solution, `None` if solving failed.
+    pub async fn solved(&mut self) -> Option<u64> {
+        (&mut self.handle).await.ok().flatten()
+    }
+}
+
+impl Drop for ProofOfWorkTask {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
@@ -1,16 +1,23 @@
+use crate::client::profile::ClientProfile;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-mod authentication;
+pub mod authentication;
+pub mod connection;
 
 #[derive(Clone)]
 pub struct ClientState {
     pub auth: Arc<Mutex<authentication::AuthenticationState>>,
+    pub connection: Arc<Mutex<connection::ConnectionState>>,
+    pub profile: Arc<Mutex<ClientProfile>>,
 }
 
 impl ClientState {
-    pub fn initialize() -> Self {
+    pub fn initialize(profile: ClientProfile) -> Self {
         Self {
             auth: Arc::new(Mutex::new(authentication::AuthenticationState::default())),
+            connection: Arc::new(Mutex::new(connection::ConnectionState::default())),
+            profile: Arc::new(Mutex::new(profile)),
         }
     }
 
@@ -19,8 +26,39 @@ impl ClientState {
         f(&guard)
     }
 
-    pub fn write_auth(&self, f: impl FnOnce(&mut authentication::AuthenticationState)) {
+    pub fn write_auth<T>(
+        &self,
+        f: impl FnOnce(&mut authentication::AuthenticationState) -> T,
+    ) -> T {
         let mut guard = self.auth.lock().unwrap();
+        f(&mut guard)
+    }
+
+    pub fn read_connection<T>(&self, f: impl FnOnce(&connection::ConnectionState) -> T) -> T {
+        let guard = self.connection.lock().unwrap();
+        f(&guard)
+    }
+
+    pub fn write_connection(&self, f: impl FnOnce(&mut connection::ConnectionState)) {
+        let mut guard = self.connection.lock().unwrap();
         f(&mut guard);
     }
+
+    pub fn status(&self) -> connection::ConnectionStatus {
+        self.read_connection(|connection| connection.status())
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.read_connection(|connection| connection.latency())
+    }
+
+    pub fn read_profile<T>(&self, f: impl FnOnce(&ClientProfile) -> T) -> T {
+        let guard = self.profile.lock().unwrap();
+        f(&guard)
+    }
+
+    pub fn write_profile<T>(&self, f: impl FnOnce(&mut ClientProfile) -> T) -> T {
+        let mut guard = self.profile.lock().unwrap();
+        f(&mut guard)
+    }
 }
@@ -1,3 +1,5 @@
+use crate::crypto::private_key::PrivateKey;
+use crate::crypto::public_key::PublicKey;
 use crate::crypto::registration_challenge::RegistrationChallenge;
 
 #[derive(Default)]
@@ -6,18 +8,49 @@ pub enum AuthenticationState {
     Unauthenticated,
     RegistrationChallengePending {
         original_challenge: RegistrationChallenge,
+        invite_code: PublicKey,
+        username: String,
+    },
+    RegistrationPending {
+        username: String,
+        private_key: PrivateKey,
+    },
+    LoginPending,
+    Authenticated {
+        username: String,
     },
 }
 
 impl AuthenticationState {
     pub fn can_register(&self) -> bool {
+        matches!(self, Self::Unauthenticated)
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        matches!(self, Self::Authenticated { .. })
+    }
+
+    pub fn username(&self) -> Option<&str> {
         match self {
-            Self::Unauthenticated => true,
-            _ => false,
+            Self::Authenticated { username } => Some(username),
+            _ => None,
         }
     }
 
-    pub fn set_authentication_pending(&mut self, original_challenge: RegistrationChallenge) {
-        *self = Self::RegistrationChallengePending { original_challenge }
+    pub fn reset(&mut self) {
+        *self = Self::Unauthenticated;
+    }
+
+    pub fn set_authentication_pending(
+        &mut self,
+        original_challenge: RegistrationChallenge,
+        invite_code: PublicKey,
+        username: String,
+    ) {
+        *self = Self::RegistrationChallengePending {
+            original_challenge,
+            invite_code,
+            username,
+        }
     }
 }
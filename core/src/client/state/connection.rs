@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    #[default]
+    Disconnected,
+    Connecting,
+    /// Connected and the server identity is verified, but not logged in.
+    Connected,
+    Authenticated,
+    ShuttingDown,
+}
+
+#[derive(Default)]
+pub struct ConnectionState {
+    status: ConnectionStatus,
+    latency: Option<Duration>,
+}
+
+impl ConnectionState {
+    pub fn status(&self) -> ConnectionStatus {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: ConnectionStatus) {
+        if status != ConnectionStatus::Connected && status != ConnectionStatus::Authenticated {
+            self.latency = None;
+        }
+        self.status = status;
+    }
+
+    /// Most recently measured round-trip time to the server.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = Some(latency);
+    }
+}
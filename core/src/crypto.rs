@@ -1,12 +1,14 @@
 use crate::crypto::private_key::PrivateKey;
 use crate::crypto::public_key::PublicKey;
 use crate::error::ErebusResult;
-use base64::prelude::BASE64_URL_SAFE_NO_PAD;
 use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
 use sha2::{Digest, Sha256};
 
+pub mod identity_proof;
 pub mod password;
 pub mod private_key;
+pub mod proof_of_work;
 pub mod public_key;
 pub mod registration_challenge;
 
@@ -0,0 +1,170 @@
+use crate::crypto::private_key::PrivateKey;
+use crate::crypto::public_key::PublicKey;
+use bincode::{Decode, Encode};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const DOMAIN: &[u8] = b"erebus identity proof v1";
+
+/// Proof that the server holds the private half of its identity key, bound to one handshake.
+///
+/// It is an HMAC of the handshake transcript (protocol version, identity key and the ephemeral
+/// keys of both sides) keyed with the Diffie-Hellman secrets of the client's ephemeral key with
+/// the identity key and with the server's ephemeral key. The server never decrypts anything
+/// for the client, and a proof is worthless for any other handshake.
+#[derive(Clone, Encode, Decode)]
+pub struct IdentityProof([u8; 32]);
+
+impl IdentityProof {
+    /// Computes the proof on the server.
+    pub fn create(
+        identity: &PrivateKey,
+        server_ephemeral: &PrivateKey,
+        client_ephemeral: &PublicKey,
+        protocol_version: u16,
+    ) -> Self {
+        let identity_secret = identity
+            .get_secret()
+            .diffie_hellman(client_ephemeral.get_key());
+        let ephemeral_secret = server_ephemeral
+            .get_secret()
+            .diffie_hellman(client_ephemeral.get_key());
+
+        let mac = transcript_mac(
+            [identity_secret.as_bytes(), ephemeral_secret.as_bytes()],
+            &identity.public_key(),
+            &server_ephemeral.public_key(),
+            client_ephemeral,
+            protocol_version,
+        );
+        Self(mac.finalize().into_bytes().into())
+    }
+
+    /// Checks the proof on the client, in constant time.
+    pub fn verify(
+        &self,
+        client_ephemeral: &PrivateKey,
+        identity: &PublicKey,
+        server_ephemeral: &PublicKey,
+        protocol_version: u16,
+    ) -> bool {
+        let secret = client_ephemeral.get_secret();
+        let identity_secret = secret.diffie_hellman(identity.get_key());
+        let ephemeral_secret = secret.diffie_hellman(server_ephemeral.get_key());
+
+        transcript_mac(
+            [identity_secret.as_bytes(), ephemeral_secret.as_bytes()],
+            identity,
+            server_ephemeral,
+            &client_ephemeral.public_key(),
+            protocol_version,
+        )
+        .verify_slice(&self.0)
+        .is_ok()
+    }
+}
+
+fn transcript_mac(
+    secrets: [&[u8; 32]; 2],
+    identity: &PublicKey,
+    server_ephemeral: &PublicKey,
+    client_ephemeral: &PublicKey,
+    protocol_version: u16,
+) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&[*secrets[0], *secrets[1]].concat())
+        .expect("HMAC accepts keys of any length");
+    mac.update(DOMAIN);
+    mac.update(&protocol_version.to_le_bytes());
+    mac.update(identity.get_key().as_bytes());
+    mac.update(server_ephemeral.get_key().as_bytes());
+    mac.update(client_ephemeral.get_key().as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROTOCOL_VERSION: u16 = 1;
+
+    struct Handshake {
+        identity: PrivateKey,
+        server_ephemeral: PrivateKey,
+        client_ephemeral: PrivateKey,
+    }
+
+    impl Handshake {
+        fn new() -> Self {
+            Self {
+                identity: PrivateKey::generate(),
+                server_ephemeral: PrivateKey::generate(),
+                client_ephemeral: PrivateKey::generate(),
+            }
+        }
+
+        fn proof(&self) -> IdentityProof {
+            IdentityProof::create(
+                &self.identity,
+                &self.server_ephemeral,
+                &self.client_ephemeral.public_key(),
+                PROTOCOL_VERSION,
+            )
+        }
+
+        fn verify(&self, proof: &IdentityProof) -> bool {
+            proof.verify(
+                &self.client_ephemeral,
+                &self.identity.public_key(),
+                &self.server_ephemeral.public_key(),
+                PROTOCOL_VERSION,
+            )
+        }
+    }
+
+    #[test]
+    fn proof_verifies_for_its_handshake() {
+        let handshake = Handshake::new();
+        assert!(handshake.verify(&handshake.proof()));
+    }
+
+    #[test]
+    fn proof_from_another_identity_is_rejected() {
+        let handshake = Handshake::new();
+        let proof = IdentityProof::create(
+            &PrivateKey::generate(),
+            &handshake.server_ephemeral,
+            &handshake.client_ephemeral.public_key(),
+            PROTOCOL_VERSION,
+        );
+
+        assert!(!handshake.verify(&proof));
+    }
+
+    #[test]
+    fn proof_for_another_ephemeral_key_is_rejected() {
+        let handshake = Handshake::new();
+        let proof = handshake.proof();
+
+        assert!(!proof.verify(
+            &handshake.client_ephemeral,
+            &handshake.identity.public_key(),
+            &PrivateKey::generate().public_key(),
+            PROTOCOL_VERSION,
+        ));
+        assert!(!proof.verify(
+            &PrivateKey::generate(),
+            &handshake.identity.public_key(),
+            &handshake.server_ephemeral.public_key(),
+            PROTOCOL_VERSION,
+        ));
+    }
+
+    #[test]
+    fn tampered_proof_is_rejected() {
+        let handshake = Handshake::new();
+        let IdentityProof(mut bytes) = handshake.proof();
+        bytes[0] ^= 1;
+
+        assert!(!handshake.verify(&IdentityProof(bytes)));
+    }
+}
@@ -1,46 +1,132 @@
 use crate::crypto::{encode_base64, sha256_bytes};
 use crate::error::{ErebusError, ErebusResult};
-use argon2::Argon2;
+use argon2::{Algorithm, Argon2, Params, Version};
 use bincode::{Decode, Encode};
-use chacha20poly1305::aead::Aead;
+use chacha20poly1305::aead::{Aead, Payload};
 use chacha20poly1305::{AeadCore, ChaCha20Poly1305, KeyInit, Nonce};
-use rand_core::OsRng;
-use zeroize::Zeroizing;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-#[derive(Encode, Decode)]
+/// Symmetric key, wiped from memory when dropped.
+#[derive(Encode, Decode, Zeroize, ZeroizeOnDrop)]
 pub struct Password([u8; 32]);
 
+/// Argon2id cost of deriving a [`Password`] from a passphrase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfCost {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+/// Everything besides the passphrase needed to derive a [`Password`], safe to store in the
+/// clear.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub salt: [u8; 32],
+    pub cost: KdfCost,
+}
+
+impl Default for KdfCost {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfCost {
+    pub fn is_valid(&self) -> bool {
+        self.params().is_ok()
+    }
+
+    fn params(&self) -> Result<Params, argon2::Error> {
+        Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+    }
+}
+
+impl KdfParams {
+    /// Parameters with a fresh random salt.
+    pub fn generate(cost: KdfCost) -> Self {
+        let mut salt = [0u8; 32];
+        OsRng.fill_bytes(&mut salt);
+        Self { salt, cost }
+    }
+}
+
 impl Password {
     pub fn new(bytes: [u8; 32]) -> Self {
         Self(bytes)
     }
 
+    /// A key of random bytes.
+    pub fn generate() -> Self {
+        let mut key = Self([0; 32]);
+        OsRng.fill_bytes(&mut key.0);
+        key
+    }
+
+    /// `None` unless `bytes` is exactly as long as a key.
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        Some(Self(bytes.try_into().ok()?))
+    }
+
+    /// Derives the key with Argon2id from `password` and `params`.
+    pub fn derive(password: &Zeroizing<String>, params: &KdfParams) -> Option<Self> {
+        let argon2 = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            params.cost.params().ok()?,
+        );
+        let mut key = Self([0; 32]);
+
+        argon2
+            .hash_password_into(password.as_bytes(), &params.salt, &mut key.0)
+            .ok()?;
+
+        Some(key)
+    }
+
+    /// Derivation of databases created before they stored [`KdfParams`]. The salt depends on
+    /// nothing but the password, so equal passwords give equal keys everywhere.
     pub fn from_string(password: Zeroizing<String>) -> Option<Self> {
         let salt: [u8; 32] = sha256_bytes(password.as_bytes());
         let argon2 = Argon2::default();
-        let mut key = [0u8; 32];
+        let mut key = Self([0; 32]);
 
         argon2
-            .hash_password_into(password.as_bytes(), &salt, &mut key)
+            .hash_password_into(password.as_bytes(), &salt, &mut key.0)
             .ok()?;
 
-        Some(Self(key))
+        Some(key)
     }
 
-    pub fn from_env(key: &str) -> Option<Self> {
-        std::env::var(key)
-            .ok()
-            .and_then(|password| Self::from_string(password.into()))
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn encrypt(&self, plaintext: &[u8]) -> ErebusResult<Vec<u8>> {
+        self.encrypt_with_aad(plaintext, &[])
     }
 
+    /// Encrypts `plaintext` authenticating `aad` along with it. The ciphertext only decrypts
+    /// with the same `aad`, which ties it to the context it was written for.
     #[tracing::instrument(level = "trace", skip_all)]
-    pub fn encrypt(&self, plaintext: &[u8]) -> ErebusResult<Vec<u8>> {
+    pub fn encrypt_with_aad(&self, plaintext: &[u8], aad: &[u8]) -> ErebusResult<Vec<u8>> {
         let Ok(cipher) = ChaCha20Poly1305::new_from_slice(&self.0) else {
             return Err(ErebusError::Encryption);
         };
 
         let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
-        let Ok(ciphertext) = cipher.encrypt(&nonce, plaintext) else {
+        let Ok(ciphertext) = cipher.encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        ) else {
             return Err(ErebusError::Encryption);
         };
 
@@ -52,22 +138,52 @@ impl Password {
 
     #[tracing::instrument(level = "trace", skip_all)]
     pub fn decrypt(&self, encrypted: &[u8]) -> ErebusResult<Vec<u8>> {
+        self.decrypt_with_aad(encrypted, &[])
+    }
+
+    /// Reverses [`Self::encrypt_with_aad`], failing if `aad` differs from the one used to encrypt.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn decrypt_with_aad(&self, encrypted: &[u8], aad: &[u8]) -> ErebusResult<Vec<u8>> {
         if encrypted.len() < 28 {
             return Err(ErebusError::Decryption);
         }
 
         let (nonce_bytes, ciphertext) = encrypted.split_at(12);
-        let nonce = Nonce::from_slice(nonce_bytes);
+        let nonce_bytes: [u8; 12] = nonce_bytes
+            .try_into()
+            .map_err(|_| ErebusError::Decryption)?;
+        let nonce = Nonce::from(nonce_bytes);
 
         let Ok(cipher) = ChaCha20Poly1305::new_from_slice(&self.0) else {
             return Err(ErebusError::Decryption);
         };
 
         cipher
-            .decrypt(nonce, ciphertext)
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
             .map_err(|_| ErebusError::Decryption)
     }
 
+    /// HMAC-SHA256 of `data` under this password, separated by `domain` so equal data hashes
+    /// differently in different places. Lets lookups work without storing `data` in plaintext.
+    pub fn keyed_hash(&self, domain: &str, data: &[u8]) -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0)
+            .expect("HMAC accepts keys of any length");
+        mac.update(&(domain.len() as u64).to_le_bytes());
+        mac.update(domain.as_bytes());
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
     pub fn as_base64(&self) -> String {
         encode_base64(&self.0)
     }
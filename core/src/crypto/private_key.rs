@@ -5,6 +5,7 @@ use rand_core::OsRng;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use x25519_dalek::StaticSecret;
 
+#[derive(Clone)]
 pub struct PrivateKey(StaticSecret);
 
 impl PrivateKey {
@@ -37,6 +38,10 @@ impl PrivateKey {
     pub fn from_bytes(bytes: [u8; 32]) -> Self {
         Self(StaticSecret::from(bytes))
     }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::generate(self)
+    }
 }
 
 impl Serialize for PrivateKey {
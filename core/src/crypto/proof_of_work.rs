@@ -0,0 +1,112 @@
+use crate::crypto::sha256_bytes;
+use bincode::{Decode, Encode};
+use rand_core::{OsRng, RngCore};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How many counters are tried between checks whether solving was cancelled.
+const CANCEL_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// Hashcash-style puzzle: find a counter for which the SHA-256 of the seed followed by the
+/// counter starts with `difficulty` zero bits. Takes about 2^difficulty hashes to solve and a
+/// single one to verify.
+#[derive(Clone, Encode, Decode)]
+pub struct WorkChallenge {
+    seed: [u8; 32],
+    pub difficulty: u8,
+}
+
+impl WorkChallenge {
+    /// Highest difficulty clients solve, about 2^32 hashes. Servers never demand more.
+    pub const MAX_DIFFICULTY: u8 = 32;
+
+    pub fn generate(difficulty: u8) -> Self {
+        let mut seed = [0; 32];
+        OsRng.fill_bytes(&mut seed);
+        Self { seed, difficulty }
+    }
+
+    /// Tries counters until one solves the challenge. `None` if `cancelled` was set first, or
+    /// if no counter solves it.
+    pub fn solve(&self, cancelled: &AtomicBool) -> Option<u64> {
+        for counter in 0..=u64::MAX {
+            if counter % CANCEL_CHECK_INTERVAL == 0 && cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+            if self.verify(counter) {
+                return Some(counter);
+            }
+        }
+        None
+    }
+
+    pub fn verify(&self, counter: u64) -> bool {
+        let mut data = [0; 40];
+        data[..32].copy_from_slice(&self.seed);
+        data[32..].copy_from_slice(&counter.to_le_bytes());
+        leading_zero_bits(&sha256_bytes(&data)) >= self.difficulty as u32
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in bytes {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Solved in a few thousand hashes.
+    const DIFFICULTY: u8 = 12;
+
+    fn challenge(seed: u8) -> WorkChallenge {
+        WorkChallenge {
+            seed: [seed; 32],
+            difficulty: DIFFICULTY,
+        }
+    }
+
+    #[test]
+    fn solution_verifies() {
+        let challenge = WorkChallenge::generate(DIFFICULTY);
+        let counter = challenge.solve(&AtomicBool::new(false)).unwrap();
+
+        assert!(challenge.verify(counter));
+    }
+
+    #[test]
+    fn wrong_counter_is_rejected() {
+        let challenge = challenge(1);
+        let counter = challenge.solve(&AtomicBool::new(false)).unwrap();
+
+        assert!(!challenge.verify(counter + 1));
+        assert!(!(0..counter).any(|counter| challenge.verify(counter)));
+    }
+
+    #[test]
+    fn solution_of_another_challenge_is_rejected() {
+        let counter = challenge(1).solve(&AtomicBool::new(false)).unwrap();
+
+        assert!(!challenge(2).verify(counter));
+    }
+
+    #[test]
+    fn cancelled_solve_gives_up() {
+        let challenge = WorkChallenge::generate(WorkChallenge::MAX_DIFFICULTY);
+
+        assert_eq!(challenge.solve(&AtomicBool::new(true)), None);
+    }
+
+    #[test]
+    fn leading_zero_bits_span_bytes() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x10]), 11);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+}
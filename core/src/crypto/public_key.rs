@@ -1,7 +1,8 @@
-use crate::crypto::encode_base64;
 use crate::crypto::password::Password;
 use crate::crypto::private_key::PrivateKey;
+use crate::crypto::{encode_base64, sha256_bytes};
 use crate::error::ErebusResult;
+use crate::formatting::format_fingerprint;
 use bincode::de::read::Reader;
 use bincode::de::Decoder;
 use bincode::enc::write::Writer;
@@ -10,6 +11,7 @@ use bincode::error::{DecodeError, EncodeError};
 use bincode::{BorrowDecode, Decode, Encode};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+#[derive(Clone, PartialEq, Eq)]
 pub struct PublicKey(x25519_dalek::PublicKey);
 
 impl PublicKey {
@@ -44,6 +46,11 @@ impl PublicKey {
     pub fn as_base64(&self) -> String {
         encode_base64(&self.0.to_bytes())
     }
+
+    /// Human-readable SHA-256 fingerprint, meant for out-of-band comparison.
+    pub fn fingerprint(&self) -> String {
+        format_fingerprint(&sha256_bytes(&self.0.to_bytes()))
+    }
 }
 
 impl Serialize for PublicKey {
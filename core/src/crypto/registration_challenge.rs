@@ -2,10 +2,10 @@ use crate::crypto::decode_base64;
 use crate::crypto::private_key::PrivateKey;
 use crate::crypto::public_key::PublicKey;
 use crate::error::{ErebusError, ErebusResult};
-use bincode::{BorrowDecode, Decode, Encode};
+use bincode::{Decode, Encode};
 use rand_core::{OsRng, RngCore};
 
-#[derive(Encode, Decode)]
+#[derive(Clone, Encode, Decode)]
 pub struct RegistrationChallenge(Vec<u8>);
 
 impl RegistrationChallenge {
@@ -1,134 +1,511 @@
-use crate::crypto::password::Password;
-use crate::database::entity::{Entity, MultiEntity};
+use crate::crypto::password::{KdfCost, KdfParams, Password};
+use crate::database::data_key::DataKey;
+use crate::database::entity::{Entity, Index, MultiEntity, StoredKey};
+use crate::database::migration::{
+    FormatUpgrade, Migrations, PendingMigration, Schema, UpgradePlan,
+};
 use crate::database::pw_verify::PasswordVerifier;
+use crate::database::query::{EntityIter, Page, Query};
+use crate::database::snapshot::{Snapshot, open_multimap_or_empty, open_table_or_empty};
+use crate::database::transaction::{Transaction, index_table_def};
 use crate::error::{ErebusError, ErebusResult};
-use redb::{ReadableDatabase, ReadableMultimapTable, ReadableTable, ReadableTableMetadata};
+use redb::{
+    MultimapTableDefinition, MultimapTableHandle, ReadableDatabase, ReadableMultimapTable,
+    ReadableTable, ReadableTableMetadata, TableDefinition, TableHandle,
+};
+use std::ops::{Bound, RangeBounds};
 use std::path::Path;
 use std::sync::Arc;
+use tracing::info;
+use zeroize::Zeroizing;
 
+pub mod data_key;
 pub mod entity;
+pub mod envelope;
+mod header;
+pub mod migration;
 mod pw_verify;
+pub mod query;
+mod snapshot;
+pub mod transaction;
 
 pub struct Database {
     db: redb::Database,
-    password: Arc<Password>,
+    data_key: Arc<DataKey>,
 }
 
 impl Database {
-    pub fn initialize(path: &Path) -> ErebusResult<Self> {
-        let create_new = !path.exists();
-        let password =
-            Password::from_env("DATABASE_PASSWORD").ok_or(ErebusError::DatabasePassword)?;
+    /// Opens the database and runs the pending `migrations`. `kdf_cost` applies when the
+    /// database key is derived anew, which is when the database is created.
+    pub fn initialize(
+        path: &Path,
+        kdf_cost: KdfCost,
+        migrations: &Migrations,
+    ) -> ErebusResult<Self> {
+        let db = Self::open(path, kdf_cost, migrations)?;
+        db.run_migrations(migrations)?;
+        Ok(db)
+    }
+
+    /// Opens the database and upgrades how it is stored, see [`Self::initialize`] for also
+    /// running the entity migrations. Databases whose key depends on the password alone move
+    /// to a random data key, for which their records are first migrated to the entities of
+    /// `migrations`.
+    pub fn open(path: &Path, kdf_cost: KdfCost, migrations: &Migrations) -> ErebusResult<Self> {
+        Self::open_with_passphrase(path, &passphrase()?, kdf_cost, migrations)
+    }
 
+    /// Like [`Self::open`], with the passphrase given instead of read from `DATABASE_PASSWORD`.
+    fn open_with_passphrase(
+        path: &Path,
+        passphrase: &Zeroizing<String>,
+        kdf_cost: KdfCost,
+        migrations: &Migrations,
+    ) -> ErebusResult<Self> {
         let redb = redb::Database::create(path)?;
-        let db = Self {
+
+        if is_uninitialized(&redb)? {
+            let kdf = KdfParams::generate(kdf_cost);
+            let db = Self {
+                db: redb,
+                data_key: Arc::new(DataKey::generate(0)),
+            };
+            let wrapped = db.data_key.wrap(&derive_password(passphrase, &kdf)?)?;
+            db.transaction(|txn| {
+                header::write_keys(txn.raw(), &kdf, &wrapped)?;
+                txn.save(&PasswordVerifier::new())?;
+                txn.save(&Schema::new())
+            })?;
+            return Ok(db);
+        }
+
+        let (data_key, upgrades) = {
+            let txn = redb.begin_read()?;
+            let (data_key, key_upgrade) = unlock(&txn, passphrase)?;
+            let mut upgrades = Snapshot::new(txn, &data_key).format_upgrades()?;
+            upgrades.extend(key_upgrade);
+            (data_key, upgrades)
+        };
+        let mut db = Self {
             db: redb,
-            password: Arc::new(password),
+            data_key: Arc::new(data_key),
         };
 
-        if create_new {
-            db.create_password_verifier()?;
+        for upgrade in upgrades {
+            info!("Upgrading the database: {}", upgrade.description());
+            match upgrade {
+                FormatUpgrade::HashedKeys => db.migrate_plaintext_keys()?,
+                FormatUpgrade::BoundValues => db.migrate_unbound_values()?,
+                FormatUpgrade::SchemaVersion => db.save(&Schema::new())?,
+                FormatUpgrade::RandomDataKey => {
+                    db.run_migrations(migrations)?;
+                    let data_key = DataKey::generate(db.data_key.id + 1);
+                    db.change_keys(passphrase, kdf_cost, Some(data_key), migrations)?;
+                }
+                FormatUpgrade::WrappedDataKey => {
+                    db.change_keys(passphrase, kdf_cost, None, migrations)?
+                }
+            }
         }
-        db.verify_password()?;
-
         Ok(db)
     }
 
-    fn create_password_verifier(&self) -> ErebusResult<()> {
-        let pw_verifier = PasswordVerifier::new();
-        self.save(&pw_verifier)?;
+    /// What opening the database at `path` and running `migrations` would change, worked out
+    /// from a read-only handle without writing anything.
+    pub fn plan(path: &Path, migrations: &Migrations) -> ErebusResult<UpgradePlan> {
+        Self::plan_with_passphrase(path, &passphrase()?, migrations)
+    }
+
+    fn plan_with_passphrase(
+        path: &Path,
+        passphrase: &Zeroizing<String>,
+        migrations: &Migrations,
+    ) -> ErebusResult<UpgradePlan> {
+        let redb = redb::ReadOnlyDatabase::open(path)?;
+        let txn = redb.begin_read()?;
+        let (data_key, key_upgrade) = unlock(&txn, passphrase)?;
+        let snapshot = Snapshot::new(txn, &data_key);
+
+        let mut format = snapshot.format_upgrades()?;
+        format.extend(key_upgrade);
+        let (migrations, _) = snapshot.plan_migrations(migrations)?;
+        Ok(UpgradePlan { format, migrations })
+    }
+
+    fn run_migrations(&self, migrations: &Migrations) -> ErebusResult<()> {
+        for migration in self.migrate(migrations)? {
+            info!(
+                "Migrated {} from v{} to v{}: {}",
+                migration.table, migration.from, migration.to, migration.description
+            );
+        }
         Ok(())
     }
 
-    fn verify_password(&self) -> ErebusResult<()> {
-        let Ok(Some(pw_verifier)) =
-            self.find::<PasswordVerifier>(PasswordVerifier::PW_VERIFY_STRING.to_string())
-        else {
-            return Err(ErebusError::DatabasePassword);
-        };
+    /// Wraps the data key with a key derived from `new_passphrase`, which the database has to
+    /// be opened with from then on. With `reencrypt` every record also moves to a new random
+    /// data key.
+    pub fn rotate_password(
+        &mut self,
+        new_passphrase: &Zeroizing<String>,
+        kdf_cost: KdfCost,
+        reencrypt: bool,
+        migrations: &Migrations,
+    ) -> ErebusResult<()> {
+        let data_key = reencrypt.then(|| DataKey::generate(self.data_key.id + 1));
+        self.change_keys(new_passphrase, kdf_cost, data_key, migrations)
+    }
 
-        if !pw_verifier.verify() {
-            Err(ErebusError::DatabasePassword)
-        } else {
-            Ok(())
+    /// Stores the data key wrapped by a key derived from `passphrase` with a fresh salt. Given
+    /// a new `data_key`, every record is first moved to it. All of it happens in a single
+    /// transaction, so a crash leaves the database entirely under the old or entirely under
+    /// the new keys. Records are moved as the entities of `migrations`, so this fails without
+    /// changes if any of their migrations are pending or if the database holds tables they
+    /// don't track.
+    fn change_keys(
+        &mut self,
+        passphrase: &Zeroizing<String>,
+        kdf_cost: KdfCost,
+        data_key: Option<DataKey>,
+        migrations: &Migrations,
+    ) -> ErebusResult<()> {
+        let rekey = data_key.is_some();
+        if rekey {
+            let pending = self.pending_migrations(migrations)?;
+            if !pending.is_empty() {
+                return Err(ErebusError::PendingMigrations(pending.len()));
+            }
+        }
+        let kdf = KdfParams::generate(kdf_cost);
+        let password = derive_password(passphrase, &kdf)?;
+        let data_key = data_key.map_or_else(|| self.data_key.clone(), Arc::new);
+
+        let txn = Transaction::new(self.db.begin_write()?, &data_key);
+        if rekey {
+            self.check_tracked(&txn, migrations)?;
+            for entity in &migrations.entities {
+                (entity.rekey)(&txn, &self.data_key)?;
+            }
+            for entity in &migrations.multi_entities {
+                (entity.rekey)(&txn, &self.data_key)?;
+            }
+            txn.rekey_all::<PasswordVerifier>(&self.data_key)?;
+            txn.rekey_all::<Schema>(&self.data_key)?;
         }
+        header::write_keys(txn.raw(), &kdf, &data_key.wrap(&password)?)?;
+        txn.commit()?;
+
+        self.data_key = data_key;
+        Ok(())
     }
 
-    fn open_table_or_empty<E: Entity>(
-        &self,
-        txn: &redb::ReadTransaction,
-    ) -> ErebusResult<Option<redb::ReadOnlyTable<E::Id, Vec<u8>>>> {
-        match txn.open_table(E::table_def()) {
-            Ok(table) => Ok(Some(table)),
-            Err(redb::TableError::TableDoesNotExist(_)) => Ok(None),
-            Err(e) => Err(e.into()),
+    fn check_tracked(&self, txn: &Transaction, migrations: &Migrations) -> ErebusResult<()> {
+        let internal = [
+            PasswordVerifier::table_name(),
+            Schema::table_name(),
+            header::HEADER_TABLE.name(),
+        ];
+        for handle in txn.raw().list_tables()? {
+            let tracked = migrations
+                .entities
+                .iter()
+                .any(|entity| entity.table == handle.name());
+            if !tracked && !internal.contains(&handle.name()) {
+                return Err(ErebusError::UnsupportedSchema(format!(
+                    "untracked table {}",
+                    handle.name()
+                )));
+            }
+        }
+
+        for handle in txn.raw().list_multimap_tables()? {
+            let tracked = migrations
+                .multi_entities
+                .iter()
+                .any(|entity| entity.table == handle.name())
+                || migrations
+                    .entities
+                    .iter()
+                    .any(|entity| entity.index_tables.iter().any(|name| name == handle.name()));
+            if !tracked {
+                return Err(ErebusError::UnsupportedSchema(format!(
+                    "untracked multimap table {}",
+                    handle.name()
+                )));
+            }
         }
+        Ok(())
     }
 
-    fn open_multimap_or_empty<E: MultiEntity>(
+    fn snapshot(&self) -> ErebusResult<Snapshot<'_>> {
+        Ok(Snapshot::new(self.db.begin_read()?, &self.data_key))
+    }
+
+    /// The steps of `migrations` which have not run yet, in the order they would run. Fails if
+    /// the database holds records of a newer version than `migrations` knows, or if the steps
+    /// don't reach the version of every tracked entity.
+    pub fn pending_migrations(
         &self,
-        txn: &redb::ReadTransaction,
-    ) -> ErebusResult<Option<redb::ReadOnlyMultimapTable<E::Id, &[u8]>>> {
-        match txn.open_multimap_table(E::multimap_table_def()) {
-            Ok(table) => Ok(Some(table)),
-            Err(redb::TableError::TableDoesNotExist(_)) => Ok(None),
-            Err(e) => Err(e.into()),
+        migrations: &Migrations,
+    ) -> ErebusResult<Vec<PendingMigration>> {
+        Ok(self.snapshot()?.plan_migrations(migrations)?.0)
+    }
+
+    /// Runs the pending steps of `migrations`, each in its own transaction, and records the
+    /// versions of the tracked entities. Returns the steps which ran.
+    pub fn migrate(&self, migrations: &Migrations) -> ErebusResult<Vec<PendingMigration>> {
+        let (pending, versions) = self.snapshot()?.plan_migrations(migrations)?;
+        for migration in &pending {
+            let step = migrations
+                .steps
+                .iter()
+                .find(|step| step.table == migration.table && step.version == migration.to)
+                .expect("pending migrations come from the steps");
+
+            self.transaction(|txn| {
+                step.run(txn)?;
+                let mut schema = txn
+                    .find::<Schema>(Schema::ID.to_string())?
+                    .unwrap_or_else(Schema::new);
+                schema.entities.insert(step.table.to_string(), step.version);
+                txn.save(&schema)
+            })?;
         }
+
+        // Records the versions of tables no step touched, which were empty or already current.
+        self.transaction(|txn| {
+            let mut schema = txn
+                .find::<Schema>(Schema::ID.to_string())?
+                .unwrap_or_else(Schema::new);
+            for entity in &migrations.entities {
+                schema
+                    .entities
+                    .insert(entity.table.to_string(), versions[entity.table]);
+            }
+            txn.save(&schema)
+        })?;
+        Ok(pending)
     }
 
-    #[tracing::instrument(level = "trace", skip_all)]
-    pub fn save<E: Entity>(&self, entity: &E) -> ErebusResult<()> {
+    /// Databases written before rows were keyed by hashed ids store the ids in the clear. The
+    /// rows are moved to their hashed keys in one transaction. Index tables are dropped and
+    /// rebuilt by [`Self::ensure_indexes`].
+    fn migrate_plaintext_keys(&self) -> ErebusResult<()> {
         let txn = self.db.begin_write()?;
-        {
-            let mut table = txn.open_table(E::table_def())?;
-            let bytes = entity.encode(&self.password)?;
-            table.insert(entity.id(), &bytes)?;
+        for handle in txn.list_tables()? {
+            let name = handle.name().to_string();
+            let rows = match txn.open_table(TableDefinition::<String, Vec<u8>>::new(&name)) {
+                Ok(table) => table
+                    .iter()?
+                    .map(|row| row.map(|(id, value)| (id.value(), value.value())))
+                    .collect::<Result<Vec<_>, _>>()?,
+                Err(redb::TableError::TableTypeMismatch { .. }) => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            txn.delete_table(handle)?;
+            let mut table = txn.open_table(TableDefinition::<StoredKey, Vec<u8>>::new(&name))?;
+            for (id, value) in rows {
+                let key = self.data_key.keyed_hash(&name, id.as_bytes());
+                table.insert(key.as_slice(), value)?;
+            }
+            info!("Moved {} rows of {}", table.len()?, name);
+        }
+
+        for handle in txn.list_multimap_tables()? {
+            let name = handle.name().to_string();
+            let definition = MultimapTableDefinition::<StoredKey, String>::new(&name);
+            match txn.open_multimap_table(definition) {
+                Ok(table) => drop(table),
+                Err(redb::TableError::TableTypeMismatch { .. }) => continue,
+                Err(e) => return Err(e.into()),
+            }
+            txn.delete_multimap_table(handle)?;
+            info!("Dropped index {}", name);
         }
         txn.commit()?;
         Ok(())
     }
 
-    #[tracing::instrument(level = "trace", skip_all)]
-    pub fn save_multi<E: MultiEntity>(&self, entity: &E) -> ErebusResult<()> {
+    /// Values used to be encrypted without associated data, so they could be moved between
+    /// rows. Every value is re-encrypted bound to its row, see [`Entity::encode`]. No multimap
+    /// entities existed then, so only tables are rewritten.
+    fn migrate_unbound_values(&self) -> ErebusResult<()> {
         let txn = self.db.begin_write()?;
-        {
-            let mut table = txn.open_multimap_table(E::multimap_table_def())?;
-            let bytes = entity.encode(&self.password)?;
-            table.insert(entity.id(), &*bytes)?;
+        for handle in txn.list_tables()? {
+            let name = handle.name().to_string();
+            let definition = TableDefinition::<StoredKey, Vec<u8>>::new(&name);
+            let mut table = match txn.open_table(definition) {
+                Ok(table) => table,
+                Err(redb::TableError::TableTypeMismatch { .. }) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let rows = table
+                .iter()?
+                .map(|row| row.map(|(key, value)| (key.value().to_vec(), value.value())))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (key, value) in &rows {
+                let plaintext = self.data_key.decrypt(value)?;
+                let value = envelope::seal(&plaintext, key, &self.data_key)?;
+                table.insert(key.as_slice(), value)?;
+            }
+            info!("Re-encrypted {} rows of {}", rows.len(), name);
         }
         txn.commit()?;
         Ok(())
     }
 
+    /// Starts a write transaction, see [`Self::transaction`] for the common case.
+    pub fn begin(&self) -> ErebusResult<Transaction<'_>> {
+        Ok(Transaction::new(self.db.begin_write()?, &self.data_key))
+    }
+
+    /// Runs `f` in a single write transaction, which is committed if `f` succeeds and aborted
+    /// otherwise, so either all or none of its writes persist.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn transaction<T, F>(&self, f: F) -> ErebusResult<T>
+    where
+        F: FnOnce(&Transaction) -> ErebusResult<T>,
+    {
+        let txn = self.begin()?;
+        let result = f(&txn)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn save<E: Entity>(&self, entity: &E) -> ErebusResult<()> {
+        self.transaction(|txn| txn.save(entity))
+    }
+
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn save_multi<E: MultiEntity>(&self, entity: &E) -> ErebusResult<()> {
+        self.transaction(|txn| txn.save_multi(entity))
+    }
+
+    /// Removes the entity with the id, returns whether it existed.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn delete<E: Entity>(&self, id: E::Id) -> ErebusResult<bool> {
+        self.transaction(|txn| txn.delete::<E>(id))
+    }
+
+    /// See [`Transaction::delete_multi_value`].
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn delete_multi_value<E: MultiEntity, F>(&self, id: E::Id, matches: F) -> ErebusResult<bool>
+    where
+        F: Fn(&E) -> bool,
+    {
+        self.transaction(|txn| txn.delete_multi_value(id, matches))
+    }
+
+    /// Reads the entity, lets `f` modify it and writes it back in a single write transaction,
+    /// so no other write can happen in between. Returns `None` if the entity doesn't exist.
+    /// `f` must not change the id of the entity.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn update<E: Entity, F>(&self, id: E::Id, f: F) -> ErebusResult<Option<E>>
+    where
+        F: FnOnce(&mut E) -> ErebusResult<()>,
+    {
+        self.transaction(|txn| {
+            let Some(mut entity) = txn.find::<E>(id)? else {
+                return Ok(None);
+            };
+
+            f(&mut entity)?;
+            txn.save(&entity)?;
+            Ok(Some(entity))
+        })
+    }
+
+    /// Like [`Self::update`], but `f` also receives `None` if the entity doesn't exist yet and
+    /// creates it.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn upsert<E: Entity, F>(&self, id: E::Id, f: F) -> ErebusResult<E>
+    where
+        F: FnOnce(Option<E>) -> ErebusResult<E>,
+    {
+        self.transaction(|txn| {
+            let entity = f(txn.find::<E>(id)?)?;
+            txn.save(&entity)?;
+            Ok(entity)
+        })
+    }
+
+    /// Removes the entity and returns it, so only one caller can ever take it.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn take<E: Entity>(&self, id: E::Id) -> ErebusResult<Option<E>> {
+        self.transaction(|txn| txn.take::<E>(id))
+    }
+
     #[tracing::instrument(level = "trace", skip_all)]
     pub fn find<E: Entity>(&self, id: E::Id) -> ErebusResult<Option<E>> {
+        self.snapshot()?.find(id)
+    }
+
+    /// Entities whose value for the index is `value`, without scanning the table.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn find_by_index<E: Entity>(&self, index: &Index, value: &str) -> ErebusResult<Vec<E>> {
         let txn = self.db.begin_read()?;
-        let Some(table) = self.open_table_or_empty::<E>(&txn)? else {
-            return Ok(None);
+        let name = E::index_table_name(index);
+        let index_table = match txn.open_multimap_table(index_table_def(&name)) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let Some(table) = open_table_or_empty::<E>(&txn)? else {
+            return Ok(Vec::new());
         };
 
-        let Some(data) = table.get(id)?.map(|guard| guard.value()) else {
-            return Ok(None);
+        let key = self.data_key.keyed_hash(&name, value.as_bytes());
+        let mut results = Vec::new();
+        for stored in index_table.get(key.as_slice())? {
+            let stored = stored?;
+            if let Some(data) = table.get(stored.value())?.map(|guard| guard.value()) {
+                results.push(E::decode(&data, stored.value(), &self.data_key)?);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Builds the indexes of `E` which have no table yet from the stored entities, so indexes
+    /// can be added to entities which already have data.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn ensure_indexes<E: Entity>(&self) -> ErebusResult<()> {
+        let missing = {
+            let txn = self.db.begin_read()?;
+            E::indexes()
+                .iter()
+                .filter(|index| {
+                    let name = E::index_table_name(index);
+                    matches!(
+                        txn.open_multimap_table(index_table_def(&name)),
+                        Err(redb::TableError::TableDoesNotExist(_))
+                    )
+                })
+                .collect::<Vec<_>>()
         };
+        if missing.is_empty() {
+            return Ok(());
+        }
 
-        let entity = E::decode(&data, &self.password)?;
-        Ok(Some(entity))
+        info!("Building {} indexes of {}", missing.len(), E::table_name());
+        self.transaction(|txn| txn.build_missing_indexes::<E>(&missing))
     }
 
     #[tracing::instrument(level = "trace", skip_all)]
     pub fn find_multi<E: MultiEntity>(&self, id: E::Id) -> ErebusResult<Vec<E>> {
         let txn = self.db.begin_read()?;
-        let table = match txn.open_multimap_table(E::multimap_table_def()) {
-            Ok(table) => table,
-            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
-            Err(e) => return Err(e.into()),
+        let Some(table) = open_multimap_or_empty::<E>(&txn)? else {
+            return Ok(Vec::new());
         };
 
+        let key = E::key(&id, &self.data_key);
         let mut results = Vec::new();
-        for item in table.get(id)? {
+        for item in table.get(key.as_slice())? {
             let guard = item?;
             let bytes = guard.value().to_vec();
-            let entity = E::decode(&bytes, &self.password)?;
+            let entity = E::decode(&bytes, &key, &self.data_key)?;
             results.push(entity);
         }
 
@@ -140,18 +517,56 @@ impl Database {
     where
         F: Fn(E) -> ErebusResult<()>,
     {
-        let txn = self.db.begin_read()?;
-        let Some(table) = self.open_table_or_empty::<E>(&txn)? else {
-            return Ok(());
-        };
+        self.iter::<E>()?.try_for_each(|entity| f(entity?))
+    }
 
-        for result in table.iter()? {
-            let (_key, guard) = result?;
-            let entity = E::decode(&guard.value(), &self.password)?;
-            f(entity)?;
+    /// All entities of the table in stored order, decoded as the iterator advances.
+    pub fn iter<E: Entity>(&self) -> ErebusResult<EntityIter<E>> {
+        self.iter_within::<E>((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// Entities whose id lies within `range`, in id order. Rows are stored under keyed hashes
+    /// of the ids so the file doesn't reveal them or their order, which also means no range of
+    /// ids maps to a range of rows: this decodes the whole table. Page large tables with
+    /// [`Self::query`] instead.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn range<E, R>(&self, range: R) -> ErebusResult<Vec<E>>
+    where
+        E: Entity,
+        E::Id: Ord,
+        R: RangeBounds<E::Id>,
+    {
+        let mut entities = Vec::new();
+        for entity in self.iter::<E>()? {
+            let entity = entity?;
+            let id = entity.id();
+            if range.contains(&id) {
+                entities.push((id, entity));
+            }
         }
+        entities.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entities.into_iter().map(|(_, entity)| entity).collect())
+    }
 
-        Ok(())
+    fn iter_within<E: Entity>(
+        &self,
+        (start, end): (Bound<[u8; 32]>, Bound<[u8; 32]>),
+    ) -> ErebusResult<EntityIter<E>> {
+        let txn = self.db.begin_read()?;
+        let start = start.as_ref().map(|key| key.as_slice());
+        let end = end.as_ref().map(|key| key.as_slice());
+        let range = match open_table_or_empty::<E>(&txn)? {
+            Some(table) => Some(table.range::<&[u8]>((start, end))?),
+            None => None,
+        };
+        Ok(EntityIter::new(range, self.data_key.clone()))
+    }
+
+    /// Collects the page of entities selected by `query`.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn query<E: Entity>(&self, query: Query<'_, E>) -> ErebusResult<Page<E>> {
+        let iter = self.iter_within::<E>(query.bounds())?;
+        query.collect(iter)
     }
 
     #[tracing::instrument(level = "trace", skip_all)]
@@ -160,18 +575,16 @@ impl Database {
         F: Fn(E) -> ErebusResult<()>,
     {
         let txn = self.db.begin_read()?;
-        let table = match txn.open_multimap_table(E::multimap_table_def()) {
-            Ok(table) => table,
-            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(()),
-            Err(e) => return Err(e.into()),
+        let Some(table) = open_multimap_or_empty::<E>(&txn)? else {
+            return Ok(());
         };
 
         for result in table.iter()? {
-            let (_key, values) = result?;
+            let (key, values) = result?;
             for value_result in values {
                 let value = value_result?;
                 let bytes = value.value();
-                let entity = E::decode(bytes, &self.password)?;
+                let entity = E::decode(bytes, key.value(), &self.data_key)?;
                 f(entity)?;
             }
         }
@@ -183,7 +596,7 @@ impl Database {
     pub fn count<E: Entity>(&self) -> ErebusResult<u64> {
         let txn = self.db.begin_read()?;
 
-        let Some(table) = self.open_table_or_empty::<E>(&txn)? else {
+        let Some(table) = open_table_or_empty::<E>(&txn)? else {
             return Ok(0);
         };
 
@@ -194,12 +607,1197 @@ impl Database {
     pub fn count_multi<E: MultiEntity>(&self) -> ErebusResult<u64> {
         let txn = self.db.begin_read()?;
 
-        let table = match txn.open_multimap_table(E::multimap_table_def()) {
-            Ok(table) => table,
-            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(0),
-            Err(e) => return Err(e.into()),
+        let Some(table) = open_multimap_or_empty::<E>(&txn)? else {
+            return Ok(0);
         };
 
         Ok(table.len().unwrap_or(0))
     }
 }
+
+fn passphrase() -> ErebusResult<Zeroizing<String>> {
+    Ok(std::env::var("DATABASE_PASSWORD")
+        .map_err(|_| ErebusError::DatabasePassword)?
+        .into())
+}
+
+/// The data key `passphrase` gives for the database, and the upgrade its key headers need.
+/// Whether it is the right key is only known once it opened the password verifier.
+fn unlock(
+    txn: &redb::ReadTransaction,
+    passphrase: &Zeroizing<String>,
+) -> ErebusResult<(DataKey, Option<FormatUpgrade>)> {
+    match (header::read_kdf(txn)?, header::read_data_key(txn)?) {
+        (Some(kdf), Some(wrapped)) => Ok((
+            DataKey::unwrap(&wrapped, &derive_password(passphrase, &kdf)?)?,
+            None,
+        )),
+        (Some(kdf), None) => Ok((
+            DataKey::from_password(0, derive_password(passphrase, &kdf)?),
+            Some(FormatUpgrade::WrappedDataKey),
+        )),
+        // The key only depends on the password, so the records move to a random key.
+        (None, _) => Ok((
+            DataKey::from_password(
+                0,
+                Password::from_string(passphrase.clone()).ok_or(ErebusError::DatabasePassword)?,
+            ),
+            Some(FormatUpgrade::RandomDataKey),
+        )),
+    }
+}
+
+/// Whether the database holds no tables yet. That is the case for new files and for files
+/// whose initialization failed before it committed, both are initialized from scratch.
+fn is_uninitialized(redb: &redb::Database) -> ErebusResult<bool> {
+    let txn = redb.begin_read()?;
+    Ok(txn.list_tables()?.next().is_none() && txn.list_multimap_tables()?.next().is_none())
+}
+
+fn derive_password(passphrase: &Zeroizing<String>, kdf: &KdfParams) -> ErebusResult<Password> {
+    Password::derive(passphrase, kdf).ok_or(ErebusError::DatabasePassword)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use zeroize::{Zeroize, ZeroizeOnDrop};
+
+    const PASSWORD: &str = "correct horse battery staple";
+    /// The lowest cost Argon2 accepts, the tests don't need slow derivations.
+    const TEST_COST: KdfCost = KdfCost {
+        memory_kib: 8,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: String,
+        name: String,
+        count: u32,
+    }
+
+    impl Item {
+        const NAME_INDEX: Index = Index::unique("name");
+
+        fn new(id: &str, name: &str) -> Self {
+            Self {
+                id: id.to_string(),
+                name: name.to_string(),
+                count: 0,
+            }
+        }
+    }
+
+    impl Entity for Item {
+        type Id = String;
+
+        fn id(&self) -> Self::Id {
+            self.id.clone()
+        }
+
+        fn table_name() -> &'static str {
+            "items"
+        }
+
+        fn indexes() -> &'static [Index] {
+            &[Self::NAME_INDEX]
+        }
+
+        fn index_value(&self, index: &Index) -> Option<String> {
+            match index.name {
+                "name" => Some(self.name.clone()),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        id: String,
+        text: String,
+    }
+
+    impl Note {
+        fn new(id: &str, text: &str) -> Self {
+            Self {
+                id: id.to_string(),
+                text: text.to_string(),
+            }
+        }
+    }
+
+    impl MultiEntity for Note {
+        type Id = String;
+
+        fn id(&self) -> Self::Id {
+            self.id.clone()
+        }
+
+        fn multimap_table_name() -> &'static str {
+            "notes"
+        }
+    }
+
+    /// [`Item`] after a field was added to it.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct ItemV2 {
+        id: String,
+        name: String,
+        count: u32,
+        tags: Vec<String>,
+    }
+
+    impl Entity for ItemV2 {
+        type Id = String;
+        const VERSION: u32 = 2;
+
+        fn id(&self) -> Self::Id {
+            self.id.clone()
+        }
+
+        fn table_name() -> &'static str {
+            Item::table_name()
+        }
+
+        fn indexes() -> &'static [Index] {
+            Item::indexes()
+        }
+
+        fn index_value(&self, index: &Index) -> Option<String> {
+            match index.name {
+                "name" => Some(self.name.clone()),
+                _ => None,
+            }
+        }
+    }
+
+    fn migrations_v2() -> Migrations {
+        Migrations::new()
+            .entity::<ItemV2>()
+            .step::<ItemV2>(2, "Add tags to items", |txn| {
+                txn.rewrite_all(|item: Item| {
+                    Ok(ItemV2 {
+                        id: item.id,
+                        name: item.name,
+                        count: item.count,
+                        tags: Vec::new(),
+                    })
+                })?;
+                Ok(())
+            })
+    }
+
+    /// Directory for one test's database, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "erebus-db-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn db_path(&self) -> PathBuf {
+            self.0.join("db.redb")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn migrations() -> Migrations {
+        Migrations::new().entity::<Item>().multi_entity::<Note>()
+    }
+
+    fn open(dir: &TempDir) -> Database {
+        open_as(dir, PASSWORD).unwrap()
+    }
+
+    fn open_as(dir: &TempDir, passphrase: &str) -> ErebusResult<Database> {
+        open_with(dir, passphrase, &migrations())
+    }
+
+    fn open_with(
+        dir: &TempDir,
+        passphrase: &str,
+        migrations: &Migrations,
+    ) -> ErebusResult<Database> {
+        Database::open_with_passphrase(
+            &dir.db_path(),
+            &Zeroizing::new(passphrase.to_string()),
+            TEST_COST,
+            migrations,
+        )
+    }
+
+    fn legacy_password() -> Password {
+        Password::from_string(Zeroizing::new(PASSWORD.to_string())).unwrap()
+    }
+
+    /// Writes `items` the way databases stored them before rows were keyed by hashed ids:
+    /// under their plaintext ids, encrypted without associated data by the key derived from
+    /// the password alone, and indexed by an index table pointing at the ids.
+    fn write_plaintext_keyed(dir: &TempDir, items: &[Item]) {
+        let password = legacy_password();
+        let db = redb::Database::create(dir.db_path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let definition =
+                TableDefinition::<String, Vec<u8>>::new(PasswordVerifier::table_name());
+            let mut verifier = txn.open_table(definition).unwrap();
+            let value = rmp_serde::to_vec_named(&PasswordVerifier::new()).unwrap();
+            verifier
+                .insert(
+                    PasswordVerifier::PW_VERIFY_STRING.to_string(),
+                    password.encrypt(&value).unwrap(),
+                )
+                .unwrap();
+
+            let mut table = txn
+                .open_table(TableDefinition::<String, Vec<u8>>::new(Item::table_name()))
+                .unwrap();
+            let index_name = Item::index_table_name(&Item::NAME_INDEX);
+            let mut index = txn
+                .open_multimap_table(MultimapTableDefinition::<StoredKey, String>::new(
+                    &index_name,
+                ))
+                .unwrap();
+            for item in items {
+                let value = rmp_serde::to_vec_named(item).unwrap();
+                table
+                    .insert(item.id.clone(), password.encrypt(&value).unwrap())
+                    .unwrap();
+                let index_key = password.keyed_hash(&index_name, item.name.as_bytes());
+                index.insert(index_key.as_slice(), item.id.clone()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn update_modifies_existing_entities_only() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+
+        let updated = db
+            .update::<Item, _>("a".to_string(), |item| {
+                item.count += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(updated.map(|item| item.count), Some(1));
+        assert_eq!(db.find::<Item>("a".to_string()).unwrap().unwrap().count, 1);
+
+        let missing = db
+            .update::<Item, _>("b".to_string(), |_| panic!("called for a missing entity"))
+            .unwrap();
+        assert!(missing.is_none());
+        assert!(db.find::<Item>("b".to_string()).unwrap().is_none());
+    }
+
+    #[test]
+    fn update_keeps_the_entity_when_the_closure_fails() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+
+        let result = db.update::<Item, _>("a".to_string(), |item| {
+            item.count = 10;
+            Err(ErebusError::InvalidInviteCode)
+        });
+        assert!(matches!(result, Err(ErebusError::InvalidInviteCode)));
+        assert_eq!(db.find::<Item>("a".to_string()).unwrap().unwrap().count, 0);
+    }
+
+    #[test]
+    fn upsert_creates_or_modifies() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+
+        let created = db
+            .upsert::<Item, _>("a".to_string(), |existing| {
+                assert!(existing.is_none());
+                Ok(Item::new("a", "first"))
+            })
+            .unwrap();
+        assert_eq!(created, Item::new("a", "first"));
+
+        let updated = db
+            .upsert::<Item, _>("a".to_string(), |existing| {
+                let mut item = existing.expect("the entity was created");
+                item.count += 1;
+                Ok(item)
+            })
+            .unwrap();
+        assert_eq!(updated.count, 1);
+        assert_eq!(db.find::<Item>("a".to_string()).unwrap(), Some(updated));
+    }
+
+    #[test]
+    fn take_and_delete_remove_once() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+        db.save(&Item::new("b", "second")).unwrap();
+
+        assert_eq!(
+            db.take::<Item>("a".to_string()).unwrap(),
+            Some(Item::new("a", "first"))
+        );
+        assert!(db.take::<Item>("a".to_string()).unwrap().is_none());
+
+        assert!(db.delete::<Item>("b".to_string()).unwrap());
+        assert!(!db.delete::<Item>("b".to_string()).unwrap());
+        assert_eq!(db.count::<Item>().unwrap(), 0);
+    }
+
+    #[test]
+    fn delete_multi_value_removes_the_first_match_only() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save_multi(&Note::new("a", "one")).unwrap();
+        db.save_multi(&Note::new("a", "two")).unwrap();
+        db.save_multi(&Note::new("b", "one")).unwrap();
+
+        assert!(
+            db.delete_multi_value::<Note, _>("a".to_string(), |note| note.text == "one")
+                .unwrap()
+        );
+        assert!(
+            !db.delete_multi_value::<Note, _>("a".to_string(), |note| note.text == "one")
+                .unwrap()
+        );
+        assert!(
+            !db.delete_multi_value::<Note, _>("c".to_string(), |_| true)
+                .unwrap()
+        );
+
+        assert_eq!(
+            db.find_multi::<Note>("a".to_string()).unwrap(),
+            vec![Note::new("a", "two")]
+        );
+        assert_eq!(
+            db.find_multi::<Note>("b".to_string()).unwrap(),
+            vec![Note::new("b", "one")]
+        );
+    }
+
+    #[test]
+    fn transaction_writes_are_all_or_nothing() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("invite", "invite")).unwrap();
+        db.save(&Item::new("user", "taken")).unwrap();
+
+        // Like registering with a taken username: the invite is taken first, then the
+        // transaction fails and the invite has to survive.
+        let result = db.transaction(|txn| {
+            assert!(txn.take::<Item>("invite".to_string())?.is_some());
+            assert!(txn.find::<Item>("invite".to_string())?.is_none());
+            if !txn
+                .find_by_index::<Item>(&Item::NAME_INDEX, "taken")?
+                .is_empty()
+            {
+                return Err(ErebusError::UsernameTaken);
+            }
+            txn.save(&Item::new("new", "taken"))
+        });
+        assert!(matches!(result, Err(ErebusError::UsernameTaken)));
+        assert!(db.find::<Item>("invite".to_string()).unwrap().is_some());
+        assert!(db.find::<Item>("new".to_string()).unwrap().is_none());
+
+        db.transaction(|txn| {
+            txn.take::<Item>("invite".to_string())?;
+            txn.save(&Item::new("new", "free"))?;
+            txn.save_multi(&Note::new("new", "welcome"))
+        })
+        .unwrap();
+        assert!(db.find::<Item>("invite".to_string()).unwrap().is_none());
+        assert!(db.find::<Item>("new".to_string()).unwrap().is_some());
+        assert_eq!(db.count_multi::<Note>().unwrap(), 1);
+    }
+
+    #[test]
+    fn dropped_transactions_abort() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+
+        let txn = db.begin().unwrap();
+        txn.save(&Item::new("a", "first")).unwrap();
+        drop(txn);
+        assert!(db.find::<Item>("a".to_string()).unwrap().is_none());
+
+        let txn = db.begin().unwrap();
+        txn.save(&Item::new("a", "first")).unwrap();
+        txn.commit().unwrap();
+        assert!(db.find::<Item>("a".to_string()).unwrap().is_some());
+    }
+
+    #[test]
+    fn unique_indexes_refuse_duplicates() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+
+        let result = db.save(&Item::new("b", "first"));
+        assert!(matches!(
+            result,
+            Err(ErebusError::UniqueIndexViolation("name"))
+        ));
+        assert!(db.find::<Item>("b".to_string()).unwrap().is_none());
+
+        // Saving an entity again with its own value is no violation.
+        let mut item = Item::new("a", "first");
+        item.count = 1;
+        db.save(&item).unwrap();
+        assert_eq!(
+            db.find_by_index::<Item>(&Item::NAME_INDEX, "first")
+                .unwrap(),
+            vec![item]
+        );
+    }
+
+    #[test]
+    fn indexes_follow_updates_and_deletes() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+
+        db.update::<Item, _>("a".to_string(), |item| {
+            item.name = "renamed".to_string();
+            Ok(())
+        })
+        .unwrap();
+        assert!(
+            db.find_by_index::<Item>(&Item::NAME_INDEX, "first")
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            db.find_by_index::<Item>(&Item::NAME_INDEX, "renamed")
+                .unwrap()
+                .len(),
+            1
+        );
+        // The old value is free again.
+        db.save(&Item::new("b", "first")).unwrap();
+
+        assert!(db.delete::<Item>("a".to_string()).unwrap());
+        assert!(
+            db.find_by_index::<Item>(&Item::NAME_INDEX, "renamed")
+                .unwrap()
+                .is_empty()
+        );
+        db.save(&Item::new("c", "renamed")).unwrap();
+
+        db.take::<Item>("b".to_string()).unwrap();
+        assert!(
+            db.find_by_index::<Item>(&Item::NAME_INDEX, "first")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn ensure_indexes_builds_missing_index_tables() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+        db.transaction(|txn| {
+            let name = Item::index_table_name(&Item::NAME_INDEX);
+            txn.raw().delete_multimap_table(index_table_def(&name))?;
+            Ok(())
+        })
+        .unwrap();
+        assert!(
+            db.find_by_index::<Item>(&Item::NAME_INDEX, "first")
+                .unwrap()
+                .is_empty()
+        );
+
+        db.ensure_indexes::<Item>().unwrap();
+        assert_eq!(
+            db.find_by_index::<Item>(&Item::NAME_INDEX, "first")
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    /// Writes `items` the way databases stored them before values were bound to their rows:
+    /// under hashed keys, but encrypted without associated data.
+    fn write_unbound(dir: &TempDir, items: &[Item]) {
+        let password = legacy_password();
+        let db = redb::Database::create(dir.db_path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut verifier = txn.open_table(PasswordVerifier::table_def()).unwrap();
+            let id = PasswordVerifier::PW_VERIFY_STRING.to_string();
+            let value = rmp_serde::to_vec_named(&PasswordVerifier::new()).unwrap();
+            let key = password.keyed_hash(PasswordVerifier::table_name(), id.as_bytes());
+            verifier
+                .insert(key.as_slice(), password.encrypt(&value).unwrap())
+                .unwrap();
+
+            let mut table = txn.open_table(Item::table_def()).unwrap();
+            for item in items {
+                let value = rmp_serde::to_vec_named(item).unwrap();
+                let key = password.keyed_hash(Item::table_name(), item.id.as_bytes());
+                table
+                    .insert(key.as_slice(), password.encrypt(&value).unwrap())
+                    .unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    }
+
+    /// Exchanges the stored values of two rows of `E`.
+    fn swap_rows<E: Entity>(db: &Database, a: &E::Id, b: &E::Id) {
+        let a = E::key(a, &db.data_key);
+        let b = E::key(b, &db.data_key);
+        let txn = db.db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(E::table_def()).unwrap();
+            let value_a = table.get(a.as_slice()).unwrap().unwrap().value();
+            let value_b = table.get(b.as_slice()).unwrap().unwrap().value();
+            table.insert(a.as_slice(), value_b).unwrap();
+            table.insert(b.as_slice(), value_a).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    /// Lets `f` change the stored value of a row of `E`.
+    fn rewrite_row<E: Entity>(db: &Database, id: &E::Id, f: impl FnOnce(&mut Vec<u8>)) {
+        let key = E::key(id, &db.data_key);
+        let txn = db.db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(E::table_def()).unwrap();
+            let mut value = table.get(key.as_slice()).unwrap().unwrap().value();
+            f(&mut value);
+            table.insert(key.as_slice(), value).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    fn save_items(db: &Database, count: usize) -> Vec<String> {
+        for i in 0..count {
+            db.save(&Item::new(&format!("id{i}"), &format!("name{i}")))
+                .unwrap();
+        }
+        db.iter::<Item>()
+            .unwrap()
+            .map(|item| item.unwrap().id)
+            .collect()
+    }
+
+    fn ids(items: &[Item]) -> Vec<String> {
+        items.iter().map(|item| item.id.clone()).collect()
+    }
+
+    #[test]
+    fn iter_visits_every_entity_in_both_directions() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        let stored = save_items(&db, 5);
+        assert_eq!(stored.len(), 5);
+
+        let mut reversed = db
+            .iter::<Item>()
+            .unwrap()
+            .rev()
+            .map(|item| item.unwrap().id)
+            .collect::<Vec<_>>();
+        reversed.reverse();
+        assert_eq!(reversed, stored);
+    }
+
+    #[test]
+    fn query_applies_offset_limit_and_filter() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        let stored = save_items(&db, 10);
+
+        let page = db.query(Query::<Item>::new().offset(3).limit(4)).unwrap();
+        assert_eq!(ids(&page.items), stored[3..7]);
+        assert!(page.next_cursor.is_some());
+
+        let page = db
+            .query(Query::<Item>::new().reverse().offset(1).limit(2))
+            .unwrap();
+        assert_eq!(ids(&page.items), [stored[8].clone(), stored[7].clone()]);
+
+        let even = stored
+            .iter()
+            .filter(|id| id.trim_start_matches("id").parse::<u32>().unwrap() % 2 == 0)
+            .cloned()
+            .collect::<Vec<_>>();
+        let page = db
+            .query(
+                Query::<Item>::new()
+                    .filter(|item| {
+                        item.name.trim_start_matches("name").parse::<u32>().unwrap() % 2 == 0
+                    })
+                    .offset(1),
+            )
+            .unwrap();
+        assert_eq!(ids(&page.items), even[1..]);
+        assert!(page.next_cursor.is_none());
+
+        let page = db.query(Query::<Item>::new().offset(10)).unwrap();
+        assert!(page.items.is_empty());
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn cursors_page_through_every_entity_once() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        let stored = save_items(&db, 7);
+
+        for reverse in [false, true] {
+            let mut expected = stored.clone();
+            if reverse {
+                expected.reverse();
+            }
+
+            let mut seen = Vec::new();
+            let mut cursor = None;
+            loop {
+                let mut query = Query::<Item>::new().limit(3);
+                if reverse {
+                    query = query.reverse();
+                }
+                if let Some(cursor) = cursor {
+                    query = query.after(cursor);
+                }
+                let page = db.query(query).unwrap();
+                seen.extend(ids(&page.items));
+                match page.next_cursor {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+            assert_eq!(seen, expected);
+        }
+
+        // A page ending exactly on the last entity is the last page.
+        let page = db.query(Query::<Item>::new().limit(7)).unwrap();
+        assert_eq!(page.items.len(), 7);
+        assert!(page.next_cursor.is_none());
+        let page = db.query(Query::<Item>::new().limit(6)).unwrap();
+        let last = db
+            .query(
+                Query::<Item>::new()
+                    .after(page.next_cursor.unwrap())
+                    .limit(1),
+            )
+            .unwrap();
+        assert_eq!(ids(&last.items), stored[6..]);
+        assert!(last.next_cursor.is_none());
+    }
+
+    #[test]
+    fn range_selects_ids_in_order() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        save_items(&db, 10);
+
+        let items = db
+            .range::<Item, _>("id2".to_string().."id5".to_string())
+            .unwrap();
+        assert_eq!(ids(&items), ["id2", "id3", "id4"]);
+        let items = db.range::<Item, _>("id8".to_string()..).unwrap();
+        assert_eq!(ids(&items), ["id8", "id9"]);
+        assert_eq!(db.range::<Item, _>(..).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn plaintext_keyed_databases_move_to_hashed_keys() {
+        let dir = TempDir::new();
+        let items = [Item::new("a", "first"), Item::new("b", "second")];
+        write_plaintext_keyed(&dir, &items);
+
+        assert!(matches!(
+            open_as(&dir, "wrong password"),
+            Err(ErebusError::DatabasePassword)
+        ));
+        let db = open(&dir);
+        db.ensure_indexes::<Item>().unwrap();
+        for item in &items {
+            assert_eq!(
+                db.find::<Item>(item.id.clone()).unwrap().as_ref(),
+                Some(item)
+            );
+            assert_eq!(
+                db.find_by_index::<Item>(&Item::NAME_INDEX, &item.name)
+                    .unwrap(),
+                vec![item.clone()]
+            );
+        }
+        drop(db);
+
+        let db = open(&dir);
+        assert_eq!(db.count::<Item>().unwrap(), 2);
+        let txn = db.db.begin_read().unwrap();
+        let plaintext_keyed = TableDefinition::<String, Vec<u8>>::new(Item::table_name());
+        assert!(matches!(
+            txn.open_table(plaintext_keyed),
+            Err(redb::TableError::TableTypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn values_moved_to_another_row_fail_to_decrypt() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+        db.save(&Item::new("b", "second")).unwrap();
+
+        swap_rows::<Item>(&db, &"a".to_string(), &"b".to_string());
+        assert!(matches!(
+            db.find::<Item>("a".to_string()),
+            Err(ErebusError::Decryption)
+        ));
+        assert!(matches!(
+            db.find::<Item>("b".to_string()),
+            Err(ErebusError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn unbound_values_are_bound_to_their_rows() {
+        let dir = TempDir::new();
+        let items = [Item::new("a", "first"), Item::new("b", "second")];
+        write_unbound(&dir, &items);
+
+        assert!(matches!(
+            open_as(&dir, "wrong password"),
+            Err(ErebusError::DatabasePassword)
+        ));
+        let db = open(&dir);
+        db.ensure_indexes::<Item>().unwrap();
+        for item in &items {
+            assert_eq!(
+                db.find::<Item>(item.id.clone()).unwrap().as_ref(),
+                Some(item)
+            );
+            assert_eq!(
+                db.find_by_index::<Item>(&Item::NAME_INDEX, &item.name)
+                    .unwrap(),
+                vec![item.clone()]
+            );
+        }
+
+        swap_rows::<Item>(&db, &"a".to_string(), &"b".to_string());
+        assert!(matches!(
+            db.find::<Item>("a".to_string()),
+            Err(ErebusError::Decryption)
+        ));
+        drop(db);
+        open(&dir);
+    }
+
+    #[test]
+    fn envelopes_round_trip_with_and_without_compression() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        let small = Item::new("a", "first");
+        let mut large = Item::new("b", "second");
+        large.name = "second".repeat(100);
+        db.save(&small).unwrap();
+        db.save(&large).unwrap();
+
+        assert_eq!(db.find::<Item>("a".to_string()).unwrap(), Some(small));
+        assert_eq!(db.find::<Item>("b".to_string()).unwrap(), Some(large));
+    }
+
+    #[test]
+    fn envelopes_of_unknown_versions_or_ciphers_are_refused() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+        db.save(&Item::new("b", "second")).unwrap();
+
+        rewrite_row::<Item>(&db, &"a".to_string(), |value| value[3] = 2);
+        assert!(matches!(
+            db.find::<Item>("a".to_string()),
+            Err(ErebusError::UnsupportedRecord("format version"))
+        ));
+        rewrite_row::<Item>(&db, &"b".to_string(), |value| value[8] = 9);
+        assert!(matches!(
+            db.find::<Item>("b".to_string()),
+            Err(ErebusError::UnsupportedRecord("cipher"))
+        ));
+    }
+
+    #[test]
+    fn headerless_values_are_still_read() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        let item = Item::new("a", "first");
+        db.save(&item).unwrap();
+
+        let key = Item::key(&item.id, &db.data_key);
+        let payload = rmp_serde::to_vec_named(&item).unwrap();
+        let headerless = db.data_key.encrypt_with_aad(&payload, &key).unwrap();
+        rewrite_row::<Item>(&db, &item.id, |value| *value = headerless);
+        assert_eq!(db.find::<Item>(item.id.clone()).unwrap(), Some(item));
+    }
+
+    fn plan(dir: &TempDir, migrations: &Migrations) -> ErebusResult<UpgradePlan> {
+        Database::plan_with_passphrase(
+            &dir.db_path(),
+            &Zeroizing::new(PASSWORD.to_string()),
+            migrations,
+        )
+    }
+
+    #[test]
+    fn plans_are_found_without_writing() {
+        let dir = TempDir::new();
+        write_plaintext_keyed(&dir, &[Item::new("a", "first")]);
+        let before = std::fs::read(dir.db_path()).unwrap();
+
+        let planned = plan(&dir, &migrations_v2()).unwrap();
+        assert_eq!(
+            planned.format,
+            [
+                FormatUpgrade::HashedKeys,
+                FormatUpgrade::BoundValues,
+                FormatUpgrade::SchemaVersion,
+                FormatUpgrade::RandomDataKey,
+            ]
+        );
+        assert_eq!(planned.migrations.len(), 1);
+        assert_eq!(
+            (planned.migrations[0].from, planned.migrations[0].to),
+            (1, 2)
+        );
+        assert_eq!(std::fs::read(dir.db_path()).unwrap(), before);
+
+        let db = open(&dir);
+        db.migrate(&migrations()).unwrap();
+        drop(db);
+        let planned = plan(&dir, &migrations()).unwrap();
+        assert!(planned.format.is_empty());
+        assert!(planned.migrations.is_empty());
+        assert!(matches!(
+            Database::plan_with_passphrase(
+                &dir.db_path(),
+                &Zeroizing::new("wrong password".to_string()),
+                &migrations()
+            ),
+            Err(ErebusError::DatabasePassword)
+        ));
+    }
+
+    #[test]
+    fn migrations_upgrade_stored_records() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+        db.migrate(&migrations()).unwrap();
+
+        let ran = db.migrate(&migrations_v2()).unwrap();
+        assert_eq!(ran.len(), 1);
+        assert_eq!(
+            db.find::<ItemV2>("a".to_string()).unwrap().unwrap().tags,
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            db.find_by_index::<ItemV2>(&Item::NAME_INDEX, "first")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(db.pending_migrations(&migrations_v2()).unwrap().is_empty());
+
+        // Records of a newer version than the entity are refused.
+        assert!(matches!(
+            db.pending_migrations(&migrations()),
+            Err(ErebusError::UnsupportedSchema(_))
+        ));
+    }
+
+    #[test]
+    fn migrations_without_a_step_to_the_current_version_fail() {
+        let dir = TempDir::new();
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+        db.migrate(&migrations()).unwrap();
+
+        let missing_step = Migrations::new().entity::<ItemV2>();
+        assert!(matches!(
+            db.pending_migrations(&missing_step),
+            Err(ErebusError::UnsupportedSchema(_))
+        ));
+        assert!(matches!(
+            db.migrate(&missing_step),
+            Err(ErebusError::UnsupportedSchema(_))
+        ));
+        // Nothing was recorded, the step still runs once it exists.
+        assert_eq!(db.pending_migrations(&migrations_v2()).unwrap().len(), 1);
+
+        // Empty tables start at the current version without any step.
+        let dir = TempDir::new();
+        let db = open(&dir);
+        assert!(db.migrate(&missing_step).unwrap().is_empty());
+        db.save(&ItemV2 {
+            id: "a".to_string(),
+            name: "first".to_string(),
+            count: 0,
+            tags: Vec::new(),
+        })
+        .unwrap();
+        assert!(db.pending_migrations(&migrations_v2()).unwrap().is_empty());
+    }
+
+    fn read_kdf(dir: &TempDir) -> Option<KdfParams> {
+        let db = redb::ReadOnlyDatabase::open(dir.db_path()).unwrap();
+        header::read_kdf(&db.begin_read().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn new_databases_derive_their_key_with_a_random_salt() {
+        let first = TempDir::new();
+        let second = TempDir::new();
+        drop(open(&first));
+        drop(open(&second));
+
+        let kdf = read_kdf(&first).unwrap();
+        assert_eq!(kdf.cost, TEST_COST);
+        assert_ne!(kdf.salt, read_kdf(&second).unwrap().salt);
+        assert!(matches!(
+            open_as(&first, "wrong password"),
+            Err(ErebusError::DatabasePassword)
+        ));
+    }
+
+    #[test]
+    fn unsalted_databases_move_to_a_salted_key() {
+        let dir = TempDir::new();
+        let items = [Item::new("a", "first"), Item::new("b", "second")];
+        write_unbound(&dir, &items);
+        assert!(read_kdf(&dir).is_none());
+
+        let db = open(&dir);
+        assert_eq!(db.data_key.id, 1);
+        drop(db);
+        assert_eq!(read_kdf(&dir).unwrap().cost, TEST_COST);
+
+        let db = open(&dir);
+        for item in &items {
+            assert_eq!(
+                db.find::<Item>(item.id.clone()).unwrap().as_ref(),
+                Some(item)
+            );
+        }
+        drop(db);
+        assert!(plan(&dir, &migrations()).unwrap().format.is_empty());
+        assert!(matches!(
+            open_as(&dir, "wrong password"),
+            Err(ErebusError::DatabasePassword)
+        ));
+    }
+
+    #[test]
+    fn salted_keys_get_wrapped() {
+        let dir = TempDir::new();
+        let item = Item::new("a", "first");
+        {
+            // Salted, but encrypting the records with the password key itself.
+            let kdf = KdfParams::generate(TEST_COST);
+            let passphrase = Zeroizing::new(PASSWORD.to_string());
+            let data_key = DataKey::from_password(0, derive_password(&passphrase, &kdf).unwrap());
+            let db = redb::Database::create(dir.db_path()).unwrap();
+            let txn = Transaction::new(db.begin_write().unwrap(), &data_key);
+            txn.raw()
+                .open_table(header::HEADER_TABLE)
+                .unwrap()
+                .insert("kdf", rmp_serde::to_vec_named(&kdf).unwrap())
+                .unwrap();
+            txn.save(&PasswordVerifier::new()).unwrap();
+            txn.save(&Schema::new()).unwrap();
+            txn.save(&item).unwrap();
+            txn.commit().unwrap();
+        }
+        assert_eq!(
+            plan(&dir, &migrations()).unwrap().format,
+            [FormatUpgrade::WrappedDataKey]
+        );
+
+        drop(open(&dir));
+        assert!(plan(&dir, &migrations()).unwrap().format.is_empty());
+        let db = open(&dir);
+        assert_eq!(db.find::<Item>(item.id.clone()).unwrap(), Some(item));
+    }
+
+    const NEW_PASSWORD: &str = "new correct horse battery staple";
+
+    fn rotate(db: &mut Database, reencrypt: bool, migrations: &Migrations) -> ErebusResult<()> {
+        db.rotate_password(
+            &Zeroizing::new(NEW_PASSWORD.to_string()),
+            TEST_COST,
+            reencrypt,
+            migrations,
+        )
+    }
+
+    #[test]
+    fn rotated_passwords_replace_the_old_ones() {
+        for reencrypt in [false, true] {
+            let dir = TempDir::new();
+            let mut db = open(&dir);
+            let item = Item::new("a", "first");
+            db.save(&item).unwrap();
+            db.save_multi(&Note::new("a", "one")).unwrap();
+
+            rotate(&mut db, reencrypt, &migrations()).unwrap();
+            assert_eq!(db.data_key.id, u32::from(reencrypt));
+            drop(db);
+
+            assert!(matches!(
+                open_as(&dir, PASSWORD),
+                Err(ErebusError::DatabasePassword)
+            ));
+            let db = open_as(&dir, NEW_PASSWORD).unwrap();
+            assert_eq!(
+                db.find::<Item>(item.id.clone()).unwrap().as_ref(),
+                Some(&item)
+            );
+            assert_eq!(
+                db.find_by_index::<Item>(&Item::NAME_INDEX, "first")
+                    .unwrap(),
+                vec![item]
+            );
+            assert_eq!(
+                db.find_multi::<Note>("a".to_string()).unwrap(),
+                vec![Note::new("a", "one")]
+            );
+        }
+    }
+
+    #[test]
+    fn reencryption_waits_for_pending_migrations() {
+        let dir = TempDir::new();
+        let mut db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+
+        assert!(matches!(
+            rotate(&mut db, true, &migrations_v2()),
+            Err(ErebusError::PendingMigrations(1))
+        ));
+        drop(db);
+        let mut db = open(&dir);
+        db.migrate(&migrations_v2()).unwrap();
+        rotate(&mut db, true, &migrations_v2()).unwrap();
+        drop(db);
+
+        let db = open_with(&dir, NEW_PASSWORD, &migrations_v2()).unwrap();
+        assert!(db.find::<ItemV2>("a".to_string()).unwrap().is_some());
+    }
+
+    #[test]
+    fn reencryption_refuses_untracked_tables() {
+        let dir = TempDir::new();
+        let mut db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+        db.save_multi(&Note::new("a", "one")).unwrap();
+
+        let without_notes = Migrations::new().entity::<Item>();
+        assert!(matches!(
+            rotate(&mut db, true, &without_notes),
+            Err(ErebusError::UnsupportedSchema(_))
+        ));
+        let without_items = Migrations::new().multi_entity::<Note>();
+        assert!(matches!(
+            rotate(&mut db, true, &without_items),
+            Err(ErebusError::UnsupportedSchema(_))
+        ));
+        drop(db);
+
+        let db = open(&dir);
+        assert_eq!(db.count::<Item>().unwrap(), 1);
+        assert_eq!(db.count_multi::<Note>().unwrap(), 1);
+    }
+
+    #[test]
+    fn unsalted_databases_are_migrated_before_they_are_rekeyed() {
+        let dir = TempDir::new();
+        write_unbound(&dir, &[Item::new("a", "first")]);
+
+        let db = open_with(&dir, PASSWORD, &migrations_v2()).unwrap();
+        assert_eq!(db.data_key.id, 1);
+        assert!(db.pending_migrations(&migrations_v2()).unwrap().is_empty());
+        assert_eq!(
+            db.find::<ItemV2>("a".to_string()).unwrap().unwrap().tags,
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn failed_initialization_is_retried() {
+        let dir = TempDir::new();
+        let invalid_cost = KdfCost {
+            memory_kib: 0,
+            ..TEST_COST
+        };
+        let failed = Database::open_with_passphrase(
+            &dir.db_path(),
+            &Zeroizing::new(PASSWORD.to_string()),
+            invalid_cost,
+            &migrations(),
+        );
+        assert!(failed.is_err());
+        assert!(dir.db_path().exists());
+
+        let db = open(&dir);
+        db.save(&Item::new("a", "first")).unwrap();
+        drop(db);
+
+        assert!(open(&dir).find::<Item>("a".to_string()).unwrap().is_some());
+        assert!(matches!(
+            open_as(&dir, "wrong password"),
+            Err(ErebusError::DatabasePassword)
+        ));
+    }
+
+    #[test]
+    fn key_material_is_wiped() {
+        fn wiped_on_drop<T: ZeroizeOnDrop>() {}
+        wiped_on_drop::<Password>();
+        wiped_on_drop::<DataKey>();
+
+        let mut data_key = DataKey::generate(7);
+        let password = derive_password(
+            &Zeroizing::new(PASSWORD.to_string()),
+            &KdfParams::generate(TEST_COST),
+        )
+        .unwrap();
+        let wrapped = data_key.wrap(&password).unwrap();
+        assert_ne!(data_key.as_bytes(), &[0; 32]);
+
+        data_key.zeroize();
+        assert_eq!(data_key.as_bytes(), &[0; 32]);
+        assert_eq!(data_key.id, 7);
+
+        let mut unwrapped = DataKey::unwrap(&wrapped, &password).unwrap();
+        assert_ne!(unwrapped.as_bytes(), &[0; 32]);
+        unwrapped.zeroize();
+        assert_eq!(unwrapped.as_bytes(), &[0; 32]);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::crypto::password::Password;
+use crate::error::{ErebusError, ErebusResult};
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Random key every record is encrypted and keyed with. It is stored wrapped by the key derived
+/// from the database password, so changing the password only rewraps it.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct DataKey {
+    /// Recorded in every [`crate::database::envelope::Envelope`], increases with every new key.
+    #[zeroize(skip)]
+    pub id: u32,
+    key: Password,
+}
+
+/// A [`DataKey`] encrypted with the password derived key.
+#[derive(Serialize, Deserialize)]
+pub(super) struct WrappedKey {
+    pub id: u32,
+    pub ciphertext: Vec<u8>,
+}
+
+impl DataKey {
+    pub fn generate(id: u32) -> Self {
+        Self {
+            id,
+            key: Password::generate(),
+        }
+    }
+
+    /// Databases created before data keys existed used the password derived key directly.
+    pub(super) fn from_password(id: u32, key: Password) -> Self {
+        Self { id, key }
+    }
+
+    pub(super) fn wrap(&self, password: &Password) -> ErebusResult<WrappedKey> {
+        Ok(WrappedKey {
+            id: self.id,
+            ciphertext: password.encrypt_with_aad(self.key.as_bytes(), &wrap_aad(self.id))?,
+        })
+    }
+
+    /// Fails with [`ErebusError::DatabasePassword`] if `password` isn't the one the key was
+    /// wrapped with.
+    pub(super) fn unwrap(wrapped: &WrappedKey, password: &Password) -> ErebusResult<Self> {
+        let bytes = password
+            .decrypt_with_aad(&wrapped.ciphertext, &wrap_aad(wrapped.id))
+            .map(Zeroizing::new)
+            .map_err(|_| ErebusError::DatabasePassword)?;
+        let key = Password::from_slice(&bytes).ok_or(ErebusError::DatabasePassword)?;
+        Ok(Self {
+            id: wrapped.id,
+            key,
+        })
+    }
+}
+
+impl Deref for DataKey {
+    type Target = Password;
+
+    fn deref(&self) -> &Password {
+        &self.key
+    }
+}
+
+fn wrap_aad(id: u32) -> Vec<u8> {
+    [b"data_key".as_slice(), &id.to_le_bytes()].concat()
+}
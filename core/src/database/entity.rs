@@ -1,47 +1,108 @@
-use crate::crypto::password::Password;
+use crate::database::data_key::DataKey;
+use crate::database::envelope;
 use crate::error::ErebusResult;
 use redb::{MultimapTableDefinition, TableDefinition};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
+/// Row keys as stored in the file, a keyed hash of the id (see [`Entity::key`]).
+pub type StoredKey = &'static [u8];
+
+/// Secondary index of an [`Entity`], kept by the database in a companion table which maps
+/// keyed hashes of the indexed values to the keys of the entities.
+pub struct Index {
+    pub name: &'static str,
+    /// Whether two entities may not share a value.
+    pub unique: bool,
+}
+
+impl Index {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            unique: false,
+        }
+    }
+
+    pub const fn unique(name: &'static str) -> Self {
+        Self { name, unique: true }
+    }
+}
+
 pub trait Entity: Sized + Serialize + for<'de> Deserialize<'de> {
-    type Id: redb::Key
-        + for<'a> std::borrow::Borrow<<Self::Id as redb::Value>::SelfType<'a>>
-        + 'static;
+    type Id: AsRef<[u8]>;
+
+    /// Version of the stored form, bump it along with a
+    /// [`crate::database::migration::Migrations::step`] when fields are renamed or retyped.
+    const VERSION: u32 = 1;
 
     fn id(&self) -> Self::Id;
     fn table_name() -> &'static str;
 
-    fn table_def() -> TableDefinition<'static, Self::Id, Vec<u8>> {
+    fn table_def() -> TableDefinition<'static, StoredKey, Vec<u8>> {
         TableDefinition::new(Self::table_name())
     }
 
-    fn encode(&self, password: &Password) -> ErebusResult<Vec<u8>> {
-        password.encrypt(&rmp_serde::to_vec_named(self)?)
+    /// The ids never reach the file, rows are stored under a hash of the id keyed by the
+    /// data key. The id itself is part of the encrypted value.
+    fn key(id: &Self::Id, data_key: &DataKey) -> [u8; 32] {
+        data_key.keyed_hash(Self::table_name(), id.as_ref())
     }
 
-    fn decode(bytes: &[u8], password: &Password) -> ErebusResult<Self> {
-        Ok(rmp_serde::from_slice(&password.decrypt(bytes)?)?)
+    /// Secondary indexes the database updates along with every write of the entity.
+    fn indexes() -> &'static [Index] {
+        &[]
+    }
+
+    /// Value of the entity for one of its [`Self::indexes`], `None` leaves it out of the index.
+    fn index_value(&self, _index: &Index) -> Option<String> {
+        None
+    }
+
+    fn index_table_name(index: &Index) -> String {
+        format!("{}_by_{}", Self::table_name(), index.name)
+    }
+
+    /// Seals the entity in an [`envelope`] for the row at `key`. The key is authenticated as
+    /// associated data, and as a hash of the table name and the id it binds the value to
+    /// exactly that row.
+    fn encode(&self, key: &[u8], data_key: &DataKey) -> ErebusResult<Vec<u8>> {
+        envelope::seal(&rmp_serde::to_vec_named(self)?, key, data_key)
+    }
+
+    /// Opens the value of the row at `key`, values moved from another row or table fail
+    /// with [`crate::error::ErebusError::Decryption`].
+    fn decode(bytes: &[u8], key: &[u8], data_key: &DataKey) -> ErebusResult<Self> {
+        Ok(rmp_serde::from_slice(&envelope::open(
+            bytes, key, data_key,
+        )?)?)
     }
 }
 
 pub trait MultiEntity: Sized + Serialize + for<'de> Deserialize<'de> + Debug {
-    type Id: redb::Key
-        + for<'a> std::borrow::Borrow<<Self::Id as redb::Value>::SelfType<'a>>
-        + 'static;
+    type Id: AsRef<[u8]>;
 
     fn id(&self) -> Self::Id;
     fn multimap_table_name() -> &'static str;
 
-    fn multimap_table_def() -> MultimapTableDefinition<'static, Self::Id, &'static [u8]> {
+    fn multimap_table_def() -> MultimapTableDefinition<'static, StoredKey, &'static [u8]> {
         MultimapTableDefinition::new(Self::multimap_table_name())
     }
 
-    fn encode(&self, password: &Password) -> ErebusResult<Vec<u8>> {
-        password.encrypt(&rmp_serde::to_vec_named(self)?)
+    /// See [`Entity::key`].
+    fn key(id: &Self::Id, data_key: &DataKey) -> [u8; 32] {
+        data_key.keyed_hash(Self::multimap_table_name(), id.as_ref())
+    }
+
+    /// See [`Entity::encode`].
+    fn encode(&self, key: &[u8], data_key: &DataKey) -> ErebusResult<Vec<u8>> {
+        envelope::seal(&rmp_serde::to_vec_named(self)?, key, data_key)
     }
 
-    fn decode(bytes: &[u8], password: &Password) -> ErebusResult<Self> {
-        Ok(rmp_serde::from_slice(&password.decrypt(bytes)?)?)
+    /// See [`Entity::decode`].
+    fn decode(bytes: &[u8], key: &[u8], data_key: &DataKey) -> ErebusResult<Self> {
+        Ok(rmp_serde::from_slice(&envelope::open(
+            bytes, key, data_key,
+        )?)?)
     }
 }
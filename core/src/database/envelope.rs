@@ -0,0 +1,141 @@
+use crate::database::data_key::DataKey;
+use crate::error::{ErebusError, ErebusResult};
+use tracing::trace;
+
+const MAGIC: [u8; 3] = *b"ERV";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 10;
+
+/// Payloads smaller than this are stored uncompressed, zstd rarely gains anything on them.
+const COMPRESSION_THRESHOLD: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Cipher {
+    ChaCha20Poly1305 = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Compression {
+    None = 0,
+    Zstd = 1,
+}
+
+/// Header in front of every stored value, so algorithms and keys can change without
+/// rewriting the database:
+///
+/// ```text
+/// magic "ERV" | format version u8 | key id u32 LE | cipher u8 | compression u8 | nonce | ciphertext
+/// ```
+///
+/// The header is authenticated along with the row key. Values written before the header
+/// existed are the bare nonce and ciphertext, and are still read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    pub key_id: u32,
+    pub cipher: Cipher,
+    pub compression: Compression,
+}
+
+impl Envelope {
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[..3].copy_from_slice(&MAGIC);
+        header[3] = FORMAT_VERSION;
+        header[4..8].copy_from_slice(&self.key_id.to_le_bytes());
+        header[8] = self.cipher as u8;
+        header[9] = self.compression as u8;
+        header
+    }
+
+    /// `None` if `bytes` don't start with a header, which means a headerless value.
+    fn parse(bytes: &[u8]) -> Option<ErebusResult<Self>> {
+        if bytes.len() < HEADER_LEN || bytes[..3] != MAGIC {
+            return None;
+        }
+        if bytes[3] != FORMAT_VERSION {
+            return Some(Err(ErebusError::UnsupportedRecord("format version")));
+        }
+
+        let cipher = match bytes[8] {
+            1 => Cipher::ChaCha20Poly1305,
+            _ => return Some(Err(ErebusError::UnsupportedRecord("cipher"))),
+        };
+        let compression = match bytes[9] {
+            0 => Compression::None,
+            1 => Compression::Zstd,
+            _ => return Some(Err(ErebusError::UnsupportedRecord("compression"))),
+        };
+        Some(Ok(Self {
+            key_id: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            cipher,
+            compression,
+        }))
+    }
+}
+
+/// Encrypts `payload` for the row at `key`, compressing it first if that makes it smaller.
+pub fn seal(payload: &[u8], key: &[u8], data_key: &DataKey) -> ErebusResult<Vec<u8>> {
+    let mut compression = Compression::None;
+    let mut compressed = None;
+    if payload.len() >= COMPRESSION_THRESHOLD {
+        let bytes = zstd::encode_all(payload, 0)?;
+        if bytes.len() < payload.len() {
+            trace!("Record compressed {} => {}", payload.len(), bytes.len());
+            compression = Compression::Zstd;
+            compressed = Some(bytes);
+        }
+    }
+
+    let header = Envelope {
+        key_id: data_key.id,
+        cipher: Cipher::ChaCha20Poly1305,
+        compression,
+    }
+    .to_bytes();
+    let plaintext = compressed.as_deref().unwrap_or(payload);
+    let ciphertext = data_key.encrypt_with_aad(plaintext, &aad(&header, key))?;
+
+    let mut result = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+    result.extend_from_slice(&header);
+    result.extend_from_slice(&ciphertext);
+    Ok(result)
+}
+
+/// Reverses [`seal`], dispatching on the header.
+pub fn open(bytes: &[u8], key: &[u8], data_key: &DataKey) -> ErebusResult<Vec<u8>> {
+    match Envelope::parse(bytes) {
+        None => data_key.decrypt_with_aad(bytes, key),
+        // The random nonce of a headerless value can start like a header, so that is tried too
+        // before giving up.
+        Some(envelope) => envelope
+            .and_then(|envelope| open_envelope(envelope, bytes, key, data_key))
+            .or_else(|error| data_key.decrypt_with_aad(bytes, key).map_err(|_| error)),
+    }
+}
+
+fn open_envelope(
+    envelope: Envelope,
+    bytes: &[u8],
+    key: &[u8],
+    data_key: &DataKey,
+) -> ErebusResult<Vec<u8>> {
+    if envelope.key_id != data_key.id {
+        return Err(ErebusError::UnsupportedRecord("key id"));
+    }
+
+    let (header, ciphertext) = bytes.split_at(HEADER_LEN);
+    let plaintext = match envelope.cipher {
+        Cipher::ChaCha20Poly1305 => data_key.decrypt_with_aad(ciphertext, &aad(header, key))?,
+    };
+
+    match envelope.compression {
+        Compression::None => Ok(plaintext),
+        Compression::Zstd => Ok(zstd::decode_all(plaintext.as_slice())?),
+    }
+}
+
+fn aad(header: &[u8], key: &[u8]) -> Vec<u8> {
+    [header, key].concat()
+}
@@ -0,0 +1,51 @@
+use crate::crypto::password::KdfParams;
+use crate::database::data_key::WrappedKey;
+use crate::error::ErebusResult;
+use redb::TableDefinition;
+use serde::Deserialize;
+
+/// Plaintext settings needed before the database key is known. Nothing secret belongs here.
+pub(super) const HEADER_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("header");
+
+const KDF: &str = "kdf";
+const DATA_KEY: &str = "data_key";
+
+/// Parameters the database key is derived with, `None` for databases created before they
+/// were stored.
+pub(super) fn read_kdf(txn: &redb::ReadTransaction) -> ErebusResult<Option<KdfParams>> {
+    read(txn, KDF)
+}
+
+/// The wrapped data key, `None` for databases created before data keys existed.
+pub(super) fn read_data_key(txn: &redb::ReadTransaction) -> ErebusResult<Option<WrappedKey>> {
+    read(txn, DATA_KEY)
+}
+
+/// Stores the parameters of the password and the data key wrapped by it, which only make
+/// sense together and so are written in the same transaction.
+pub(super) fn write_keys(
+    txn: &redb::WriteTransaction,
+    kdf: &KdfParams,
+    data_key: &WrappedKey,
+) -> ErebusResult<()> {
+    let mut table = txn.open_table(HEADER_TABLE)?;
+    table.insert(KDF, rmp_serde::to_vec_named(kdf)?)?;
+    table.insert(DATA_KEY, rmp_serde::to_vec_named(data_key)?)?;
+    Ok(())
+}
+
+fn read<T: for<'de> Deserialize<'de>>(
+    txn: &redb::ReadTransaction,
+    name: &str,
+) -> ErebusResult<Option<T>> {
+    let table = match txn.open_table(HEADER_TABLE) {
+        Ok(table) => table,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    match table.get(name)? {
+        Some(guard) => Ok(Some(rmp_serde::from_slice(&guard.value())?)),
+        None => Ok(None),
+    }
+}
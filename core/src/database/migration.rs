@@ -0,0 +1,178 @@
+use crate::database::data_key::DataKey;
+use crate::database::entity::{Entity, MultiEntity};
+use crate::database::transaction::Transaction;
+use crate::error::ErebusResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Version of the layout of the database itself: how rows are keyed, encrypted and enveloped.
+/// The layouts before versioning are upgraded when the database is opened.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// One upgrade step of an entity, run in its own write transaction along with recording
+/// `version` as the version of the entity's table.
+pub struct Migration {
+    pub table: &'static str,
+    pub version: u32,
+    pub description: &'static str,
+    run: fn(&Transaction) -> ErebusResult<()>,
+}
+
+/// Entities whose versions the database tracks and the steps between their versions, in the
+/// order they run. Only tracked entities survive a change of the database key.
+#[derive(Default)]
+pub struct Migrations {
+    pub(super) entities: Vec<TrackedEntity>,
+    pub(super) multi_entities: Vec<TrackedMultiEntity>,
+    pub(super) steps: Vec<Migration>,
+}
+
+pub(super) struct TrackedEntity {
+    pub table: &'static str,
+    pub version: u32,
+    pub index_tables: Vec<String>,
+    pub rekey: fn(&Transaction, &DataKey) -> ErebusResult<()>,
+}
+
+pub(super) struct TrackedMultiEntity {
+    pub table: &'static str,
+    pub rekey: fn(&Transaction, &DataKey) -> ErebusResult<()>,
+}
+
+impl TrackedEntity {
+    pub fn new<E: Entity>() -> Self {
+        Self {
+            table: E::table_name(),
+            version: E::VERSION,
+            index_tables: E::indexes().iter().map(E::index_table_name).collect(),
+            rekey: |txn, old| txn.rekey_all::<E>(old),
+        }
+    }
+}
+
+impl TrackedMultiEntity {
+    pub fn new<E: MultiEntity>() -> Self {
+        Self {
+            table: E::multimap_table_name(),
+            rekey: |txn, old| txn.rekey_all_multi::<E>(old),
+        }
+    }
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracks the version of `E`, so stored records written by a newer `E` are refused.
+    pub fn entity<E: Entity>(mut self) -> Self {
+        self.entities.push(TrackedEntity::new::<E>());
+        self
+    }
+
+    /// Tracks `E` so its values move along when the data key changes. Multimap entities
+    /// have no versions.
+    pub fn multi_entity<E: MultiEntity>(mut self) -> Self {
+        self.multi_entities.push(TrackedMultiEntity::new::<E>());
+        self
+    }
+
+    /// Upgrades the stored records of `E` to `version`, usually with
+    /// [`Transaction::rewrite_all`].
+    pub fn step<E: Entity>(
+        mut self,
+        version: u32,
+        description: &'static str,
+        run: fn(&Transaction) -> ErebusResult<()>,
+    ) -> Self {
+        self.steps.push(Migration {
+            table: E::table_name(),
+            version,
+            description,
+            run,
+        });
+        self
+    }
+}
+
+impl Migration {
+    pub(super) fn run(&self, txn: &Transaction) -> ErebusResult<()> {
+        (self.run)(txn)
+    }
+}
+
+/// A step of [`Migrations`] which has not run on the database yet.
+#[derive(Debug)]
+pub struct PendingMigration {
+    pub table: &'static str,
+    pub from: u32,
+    pub to: u32,
+    pub description: &'static str,
+}
+
+/// A change to how the database itself is stored, made by [`crate::database::Database::open`]
+/// before any entity migration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatUpgrade {
+    /// Rows move from their plaintext ids to keyed hashes of the ids.
+    HashedKeys,
+    /// Values are re-encrypted bound to their rows.
+    BoundValues,
+    /// The versions of the schema and of the entities start being recorded.
+    SchemaVersion,
+    /// Records move from the key derived from the password alone to a random data key.
+    RandomDataKey,
+    /// The data key gets wrapped by a password key derived with a random salt.
+    WrappedDataKey,
+}
+
+impl FormatUpgrade {
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::HashedKeys => "Move rows from plaintext ids to hashed keys",
+            Self::BoundValues => "Bind encrypted values to their rows",
+            Self::SchemaVersion => "Record the schema version",
+            Self::RandomDataKey => "Move every record to a random data key",
+            Self::WrappedDataKey => "Wrap the data key with a salted password key",
+        }
+    }
+}
+
+/// What opening a database would change, see [`crate::database::Database::plan`].
+#[derive(Debug, Default)]
+pub struct UpgradePlan {
+    pub format: Vec<FormatUpgrade>,
+    /// The entity migrations which run after the format upgrades.
+    pub migrations: Vec<PendingMigration>,
+}
+
+/// Versions recorded in the database.
+#[derive(Serialize, Deserialize)]
+pub(super) struct Schema {
+    pub version: u32,
+    /// Version of the stored records of each tracked table.
+    pub entities: BTreeMap<String, u32>,
+}
+
+impl Schema {
+    pub const ID: &'static str = "schema";
+
+    pub fn new() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            entities: BTreeMap::new(),
+        }
+    }
+}
+
+impl Entity for Schema {
+    type Id = String;
+
+    fn id(&self) -> Self::Id {
+        Self::ID.to_string()
+    }
+
+    fn table_name() -> &'static str {
+        "schema"
+    }
+}
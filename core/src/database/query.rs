@@ -0,0 +1,164 @@
+use crate::database::data_key::DataKey;
+use crate::database::entity::{Entity, StoredKey};
+use crate::error::ErebusResult;
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Position in a table for [`Query::after`]. Rows are ordered by their stored keys, which are
+/// keyed hashes of the ids, so the order is stable but unrelated to the ids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor([u8; 32]);
+
+/// Lazily decoding iterator over the entities of a table in stored order. It keeps its read
+/// transaction open until dropped, and iterates in reverse with [`Iterator::rev`].
+pub struct EntityIter<E: Entity> {
+    range: Option<redb::Range<'static, StoredKey, Vec<u8>>>,
+    data_key: Arc<DataKey>,
+    entity: PhantomData<E>,
+}
+
+impl<E: Entity> EntityIter<E> {
+    pub(super) fn new(
+        range: Option<redb::Range<'static, StoredKey, Vec<u8>>>,
+        data_key: Arc<DataKey>,
+    ) -> Self {
+        Self {
+            range,
+            data_key,
+            entity: PhantomData,
+        }
+    }
+
+    fn next_entry(&mut self, back: bool) -> Option<ErebusResult<(Cursor, E)>> {
+        let range = self.range.as_mut()?;
+        let item = if back {
+            range.next_back()?
+        } else {
+            range.next()?
+        };
+        Some(item.map_err(Into::into).and_then(|(key, guard)| {
+            let cursor = Cursor(key.value().try_into().unwrap_or_default());
+            Ok((
+                cursor,
+                E::decode(&guard.value(), key.value(), &self.data_key)?,
+            ))
+        }))
+    }
+}
+
+impl<E: Entity> Iterator for EntityIter<E> {
+    type Item = ErebusResult<E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_entry(false)?.map(|(_, entity)| entity))
+    }
+}
+
+impl<E: Entity> DoubleEndedIterator for EntityIter<E> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        Some(self.next_entry(true)?.map(|(_, entity)| entity))
+    }
+}
+
+/// Selects a page of entities for [`crate::database::Database::query`].
+pub struct Query<'f, E: Entity> {
+    after: Option<Cursor>,
+    reverse: bool,
+    offset: usize,
+    limit: Option<usize>,
+    filter: Option<Filter<'f, E>>,
+}
+
+type Filter<'f, E> = Box<dyn Fn(&E) -> bool + 'f>;
+
+/// Entities selected by a [`Query`].
+pub struct Page<E: Entity> {
+    pub items: Vec<E>,
+    /// Pass to [`Query::after`] for the next page, `None` if this was the last one.
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<'f, E: Entity> Default for Query<'f, E> {
+    fn default() -> Self {
+        Self {
+            after: None,
+            reverse: false,
+            offset: 0,
+            limit: None,
+            filter: None,
+        }
+    }
+}
+
+impl<'f, E: Entity> Query<'f, E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continues after the entity at `cursor`, in the direction of the query.
+    pub fn after(mut self, cursor: Cursor) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Reverse stored order.
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    /// Skips the first `offset` matching entities.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Only entities for which `filter` returns true, applied before offset and limit.
+    pub fn filter(mut self, filter: impl Fn(&E) -> bool + 'f) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Range of stored keys left to visit.
+    pub(super) fn bounds(&self) -> (Bound<[u8; 32]>, Bound<[u8; 32]>) {
+        match self.after {
+            Some(Cursor(after)) if self.reverse => (Bound::Unbounded, Bound::Excluded(after)),
+            Some(Cursor(after)) => (Bound::Excluded(after), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        }
+    }
+
+    pub(super) fn collect(self, mut iter: EntityIter<E>) -> ErebusResult<Page<E>> {
+        let reverse = self.reverse;
+        let mut matching = std::iter::from_fn(|| iter.next_entry(reverse)).filter(|entry| {
+            match (entry, &self.filter) {
+                (Ok((_, entity)), Some(filter)) => filter(entity),
+                _ => true,
+            }
+        });
+
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let entries = matching
+            .by_ref()
+            .skip(self.offset)
+            .take(limit)
+            .collect::<ErebusResult<Vec<_>>>()?;
+
+        let next_cursor = match entries.last() {
+            Some((cursor, _)) if entries.len() == limit && matching.next().is_some() => {
+                Some(*cursor)
+            }
+            _ => None,
+        };
+        Ok(Page {
+            items: entries.into_iter().map(|(_, entity)| entity).collect(),
+            next_cursor,
+        })
+    }
+}
@@ -0,0 +1,201 @@
+use crate::database::data_key::DataKey;
+use crate::database::entity::{Entity, MultiEntity, StoredKey};
+use crate::database::envelope;
+use crate::database::migration::{
+    FormatUpgrade, Migrations, PendingMigration, SCHEMA_VERSION, Schema,
+};
+use crate::database::pw_verify::PasswordVerifier;
+use crate::error::{ErebusError, ErebusResult};
+use redb::{ReadableTableMetadata, TableDefinition};
+use std::collections::BTreeMap;
+
+/// Read-only view of the database. Besides plain lookups it checks the password and works out
+/// what opening the database would change, so that can be found without writing to it.
+pub(super) struct Snapshot<'a> {
+    txn: redb::ReadTransaction,
+    data_key: &'a DataKey,
+}
+
+impl<'a> Snapshot<'a> {
+    pub fn new(txn: redb::ReadTransaction, data_key: &'a DataKey) -> Self {
+        Self { txn, data_key }
+    }
+
+    pub fn find<E: Entity>(&self, id: E::Id) -> ErebusResult<Option<E>> {
+        let Some(table) = open_table_or_empty::<E>(&self.txn)? else {
+            return Ok(None);
+        };
+
+        let key = E::key(&id, self.data_key);
+        let Some(data) = table.get(key.as_slice())?.map(|guard| guard.value()) else {
+            return Ok(None);
+        };
+
+        Ok(Some(E::decode(&data, &key, self.data_key)?))
+    }
+
+    /// The upgrades of how rows are keyed, encrypted and versioned which the database still
+    /// needs, in the order they run. Fails with [`ErebusError::DatabasePassword`] unless the
+    /// data key opens the password verifier as it was written.
+    pub fn format_upgrades(&self) -> ErebusResult<Vec<FormatUpgrade>> {
+        let mut upgrades = self.layout_upgrades()?;
+        if !upgrades.is_empty() {
+            // Schema versions were recorded only after the layout upgrades existed.
+            upgrades.push(FormatUpgrade::SchemaVersion);
+            return Ok(upgrades);
+        }
+
+        self.verify_password()?;
+        match self.find::<Schema>(Schema::ID.to_string())? {
+            Some(schema) if schema.version > SCHEMA_VERSION => Err(ErebusError::UnsupportedSchema(
+                format!("schema v{} is newer than v{SCHEMA_VERSION}", schema.version),
+            )),
+            Some(_) => Ok(upgrades),
+            None => Ok(vec![FormatUpgrade::SchemaVersion]),
+        }
+    }
+
+    fn layout_upgrades(&self) -> ErebusResult<Vec<FormatUpgrade>> {
+        let legacy_verifier =
+            TableDefinition::<String, Vec<u8>>::new(PasswordVerifier::table_name());
+        match self.txn.open_table(legacy_verifier) {
+            Ok(table) => {
+                let verified = table
+                    .get(PasswordVerifier::PW_VERIFY_STRING.to_string())?
+                    .and_then(|guard| self.data_key.decrypt(&guard.value()).ok())
+                    .and_then(|bytes| rmp_serde::from_slice::<PasswordVerifier>(&bytes).ok())
+                    .is_some_and(|verifier| verifier.verify());
+                if !verified {
+                    return Err(ErebusError::DatabasePassword);
+                }
+                // Values were bound to their rows only after the keys were hashed.
+                return Ok(vec![FormatUpgrade::HashedKeys, FormatUpgrade::BoundValues]);
+            }
+            Err(redb::TableError::TableTypeMismatch { .. }) => {}
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        }
+
+        let verifier_key = PasswordVerifier::key(
+            &PasswordVerifier::PW_VERIFY_STRING.to_string(),
+            self.data_key,
+        );
+        let Some(table) = open_table_or_empty::<PasswordVerifier>(&self.txn)? else {
+            return Ok(Vec::new());
+        };
+        let Some(value) = table.get(verifier_key.as_slice())? else {
+            return Ok(Vec::new());
+        };
+        let value = value.value();
+        if envelope::open(&value, &verifier_key, self.data_key).is_ok() {
+            return Ok(Vec::new());
+        }
+        if self.data_key.decrypt(&value).is_err() {
+            return Err(ErebusError::DatabasePassword);
+        }
+        Ok(vec![FormatUpgrade::BoundValues])
+    }
+
+    fn verify_password(&self) -> ErebusResult<()> {
+        let Ok(Some(pw_verifier)) =
+            self.find::<PasswordVerifier>(PasswordVerifier::PW_VERIFY_STRING.to_string())
+        else {
+            return Err(ErebusError::DatabasePassword);
+        };
+
+        if !pw_verifier.verify() {
+            Err(ErebusError::DatabasePassword)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The steps of `migrations` which have not run yet, in the order they would run, and the
+    /// version every tracked table has after them. Fails if the database holds records of a
+    /// newer version than `migrations` knows, or if the steps don't bring a table all the way
+    /// to the version of its entity.
+    pub fn plan_migrations(
+        &self,
+        migrations: &Migrations,
+    ) -> ErebusResult<(Vec<PendingMigration>, BTreeMap<&'static str, u32>)> {
+        let schema = self
+            .find::<Schema>(Schema::ID.to_string())?
+            .unwrap_or_else(Schema::new);
+
+        let mut versions = BTreeMap::new();
+        for entity in &migrations.entities {
+            let stored = self.stored_version(&schema, entity.table, entity.version)?;
+            if stored > entity.version {
+                return Err(ErebusError::UnsupportedSchema(format!(
+                    "table {} v{stored} is newer than v{}",
+                    entity.table, entity.version
+                )));
+            }
+            versions.insert(entity.table, stored);
+        }
+
+        let mut pending = Vec::new();
+        for step in &migrations.steps {
+            let from = match versions.get(step.table) {
+                Some(version) => *version,
+                None => self.stored_version(&schema, step.table, 1)?,
+            };
+            if step.version > from {
+                pending.push(PendingMigration {
+                    table: step.table,
+                    from,
+                    to: step.version,
+                    description: step.description,
+                });
+                versions.insert(step.table, step.version);
+            }
+        }
+
+        for entity in &migrations.entities {
+            let reached = versions[entity.table];
+            if reached < entity.version {
+                return Err(ErebusError::UnsupportedSchema(format!(
+                    "table {} v{reached} has no migration to v{}",
+                    entity.table, entity.version
+                )));
+            }
+        }
+        Ok((pending, versions))
+    }
+
+    /// Version of the records in `table`. Tables which were written before their version was
+    /// recorded hold version 1, empty ones can start at `current`. Tables are counted
+    /// untyped, since their keys may still be plaintext ids.
+    fn stored_version(&self, schema: &Schema, table: &str, current: u32) -> ErebusResult<u32> {
+        if let Some(version) = schema.entities.get(table) {
+            return Ok(*version);
+        }
+
+        let definition = TableDefinition::<StoredKey, Vec<u8>>::new(table);
+        match self.txn.open_untyped_table(definition) {
+            Ok(table) if !table.is_empty()? => Ok(1),
+            Ok(_) | Err(redb::TableError::TableDoesNotExist(_)) => Ok(current),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+pub(super) fn open_table_or_empty<E: Entity>(
+    txn: &redb::ReadTransaction,
+) -> ErebusResult<Option<redb::ReadOnlyTable<StoredKey, Vec<u8>>>> {
+    match txn.open_table(E::table_def()) {
+        Ok(table) => Ok(Some(table)),
+        Err(redb::TableError::TableDoesNotExist(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(super) fn open_multimap_or_empty<E: MultiEntity>(
+    txn: &redb::ReadTransaction,
+) -> ErebusResult<Option<redb::ReadOnlyMultimapTable<StoredKey, &'static [u8]>>> {
+    match txn.open_multimap_table(E::multimap_table_def()) {
+        Ok(table) => Ok(Some(table)),
+        Err(redb::TableError::TableDoesNotExist(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,274 @@
+use crate::database::data_key::DataKey;
+use crate::database::entity::{Entity, Index, MultiEntity, StoredKey};
+use crate::database::envelope;
+use crate::error::{ErebusError, ErebusResult};
+use redb::{MultimapTableDefinition, ReadableMultimapTable, ReadableTable};
+use serde::Deserialize;
+
+/// Write transaction spanning any number of entity types. Nothing is visible to readers until
+/// [`Self::commit`], dropping the transaction aborts all of its writes.
+pub struct Transaction<'db> {
+    txn: redb::WriteTransaction,
+    data_key: &'db DataKey,
+}
+
+impl<'db> Transaction<'db> {
+    pub(super) fn new(txn: redb::WriteTransaction, data_key: &'db DataKey) -> Self {
+        Self { txn, data_key }
+    }
+
+    pub fn commit(self) -> ErebusResult<()> {
+        self.txn.commit()?;
+        Ok(())
+    }
+
+    /// The underlying redb transaction, for the database's own plaintext tables.
+    pub(super) fn raw(&self) -> &redb::WriteTransaction {
+        &self.txn
+    }
+
+    pub fn abort(self) -> ErebusResult<()> {
+        self.txn.abort()?;
+        Ok(())
+    }
+
+    pub fn save<E: Entity>(&self, entity: &E) -> ErebusResult<()> {
+        let key = E::key(&entity.id(), self.data_key);
+        if !E::indexes().is_empty() {
+            self.check_unique_indexes(entity, &key)?;
+            if let Some(previous) = self.find_by_key::<E>(&key)? {
+                self.remove_from_indexes(&previous, &key)?;
+            }
+            self.add_to_indexes(entity, &key)?;
+        }
+
+        let mut table = self.txn.open_table(E::table_def())?;
+        table.insert(key.as_slice(), &entity.encode(&key, self.data_key)?)?;
+        Ok(())
+    }
+
+    pub fn save_multi<E: MultiEntity>(&self, entity: &E) -> ErebusResult<()> {
+        let key = E::key(&entity.id(), self.data_key);
+        let mut table = self.txn.open_multimap_table(E::multimap_table_def())?;
+        table.insert(key.as_slice(), &*entity.encode(&key, self.data_key)?)?;
+        Ok(())
+    }
+
+    /// Sees the writes made earlier in this transaction.
+    pub fn find<E: Entity>(&self, id: E::Id) -> ErebusResult<Option<E>> {
+        self.find_by_key(&E::key(&id, self.data_key))
+    }
+
+    fn find_by_key<E: Entity>(&self, key: &[u8]) -> ErebusResult<Option<E>> {
+        let table = self.txn.open_table(E::table_def())?;
+        let Some(data) = table.get(key)?.map(|guard| guard.value()) else {
+            return Ok(None);
+        };
+
+        Ok(Some(E::decode(&data, key, self.data_key)?))
+    }
+
+    /// Entities whose value for the index is `value`.
+    pub fn find_by_index<E: Entity>(&self, index: &Index, value: &str) -> ErebusResult<Vec<E>> {
+        let name = E::index_table_name(index);
+        let index_table = self.txn.open_multimap_table(index_table_def(&name))?;
+        let index_key = self.data_key.keyed_hash(&name, value.as_bytes());
+
+        let mut results = Vec::new();
+        for key in index_table.get(index_key.as_slice())? {
+            if let Some(entity) = self.find_by_key(key?.value())? {
+                results.push(entity);
+            }
+        }
+        Ok(results)
+    }
+
+    /// Removes the entity with the id, returns whether it existed.
+    pub fn delete<E: Entity>(&self, id: E::Id) -> ErebusResult<bool> {
+        Ok(self.take::<E>(id)?.is_some())
+    }
+
+    /// Removes the entity and returns it.
+    pub fn take<E: Entity>(&self, id: E::Id) -> ErebusResult<Option<E>> {
+        let key = E::key(&id, self.data_key);
+        let data = {
+            let mut table = self.txn.open_table(E::table_def())?;
+            table.remove(key.as_slice())?.map(|guard| guard.value())
+        };
+        let Some(data) = data else {
+            return Ok(None);
+        };
+
+        let entity = E::decode(&data, &key, self.data_key)?;
+        self.remove_from_indexes(&entity, &key)?;
+        Ok(Some(entity))
+    }
+
+    /// Removes the first value stored under `id` for which `matches` returns true, returns
+    /// whether a value was removed. Values are encrypted with a random nonce, so they can only
+    /// be matched after decoding them.
+    pub fn delete_multi_value<E: MultiEntity, F>(&self, id: E::Id, matches: F) -> ErebusResult<bool>
+    where
+        F: Fn(&E) -> bool,
+    {
+        let key = E::key(&id, self.data_key);
+        let mut table = self.txn.open_multimap_table(E::multimap_table_def())?;
+        let mut found = None;
+        for item in table.get(key.as_slice())? {
+            let bytes = item?.value().to_vec();
+            if matches(&E::decode(&bytes, &key, self.data_key)?) {
+                found = Some(bytes);
+                break;
+            }
+        }
+
+        match found {
+            Some(bytes) => Ok(table.remove(key.as_slice(), bytes.as_slice())?),
+            None => Ok(false),
+        }
+    }
+
+    /// Replaces every stored `E` by `f` applied to the record read in its previous form `Old`,
+    /// for [`crate::database::migration::Migrations::step`]. Indexes of `E` are rebuilt, and
+    /// `f` must keep the ids. Returns the number of rewritten records.
+    pub fn rewrite_all<Old, E, F>(&self, f: F) -> ErebusResult<usize>
+    where
+        Old: for<'de> Deserialize<'de>,
+        E: Entity,
+        F: Fn(Old) -> ErebusResult<E>,
+    {
+        let mut table = self.txn.open_table(E::table_def())?;
+        let mut records = Vec::new();
+        for result in table.iter()? {
+            let (key, guard) = result?;
+            let plaintext = envelope::open(&guard.value(), key.value(), self.data_key)?;
+            records.push(rmp_serde::from_slice::<Old>(&plaintext)?);
+        }
+        table.retain(|_, _| false)?;
+        drop(table);
+
+        for index in E::indexes() {
+            self.txn
+                .delete_multimap_table(index_table_def(&E::index_table_name(index)))?;
+        }
+        let count = records.len();
+        for old in records {
+            self.save(&f(old)?)?;
+        }
+        Ok(count)
+    }
+
+    /// Moves every stored `E` from the keys and encryption of `old` to those of this
+    /// transaction's data key, and rebuilds the indexes of `E`.
+    pub(super) fn rekey_all<E: Entity>(&self, old: &DataKey) -> ErebusResult<()> {
+        let mut table = self.txn.open_table(E::table_def())?;
+        let mut entities = Vec::new();
+        for result in table.iter()? {
+            let (key, guard) = result?;
+            entities.push(E::decode(&guard.value(), key.value(), old)?);
+        }
+        table.retain(|_, _| false)?;
+
+        for entity in &entities {
+            let key = E::key(&entity.id(), self.data_key);
+            table.insert(key.as_slice(), entity.encode(&key, self.data_key)?)?;
+        }
+        drop(table);
+
+        for index in E::indexes() {
+            self.txn
+                .delete_multimap_table(index_table_def(&E::index_table_name(index)))?;
+        }
+        self.build_missing_indexes::<E>(&E::indexes().iter().collect::<Vec<_>>())
+    }
+
+    /// Like [`Self::rekey_all`], for the values of a [`MultiEntity`].
+    pub(super) fn rekey_all_multi<E: MultiEntity>(&self, old: &DataKey) -> ErebusResult<()> {
+        let mut entities = Vec::new();
+        {
+            let table = self.txn.open_multimap_table(E::multimap_table_def())?;
+            for result in table.iter()? {
+                let (key, values) = result?;
+                for value in values {
+                    entities.push(E::decode(value?.value(), key.value(), old)?);
+                }
+            }
+        }
+        self.txn.delete_multimap_table(E::multimap_table_def())?;
+
+        let mut table = self.txn.open_multimap_table(E::multimap_table_def())?;
+        for entity in &entities {
+            let key = E::key(&entity.id(), self.data_key);
+            table.insert(key.as_slice(), &*entity.encode(&key, self.data_key)?)?;
+        }
+        Ok(())
+    }
+
+    /// Adds every stored entity to the indexes which don't have a table yet, for indexes
+    /// declared after the entities were written.
+    pub(super) fn build_missing_indexes<E: Entity>(&self, missing: &[&Index]) -> ErebusResult<()> {
+        let table = self.txn.open_table(E::table_def())?;
+        for result in table.iter()? {
+            let (key, guard) = result?;
+            let entity = E::decode(&guard.value(), key.value(), self.data_key)?;
+            for index in missing {
+                self.add_to_index(index, &entity, key.value())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_unique_indexes<E: Entity>(&self, entity: &E, key: &[u8]) -> ErebusResult<()> {
+        for index in E::indexes().iter().filter(|index| index.unique) {
+            let Some(value) = entity.index_value(index) else {
+                continue;
+            };
+            let name = E::index_table_name(index);
+            let table = self.txn.open_multimap_table(index_table_def(&name))?;
+            let index_key = self.data_key.keyed_hash(&name, value.as_bytes());
+
+            for other in table.get(index_key.as_slice())? {
+                if other?.value() != key {
+                    return Err(ErebusError::UniqueIndexViolation(index.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_to_indexes<E: Entity>(&self, entity: &E, key: &[u8]) -> ErebusResult<()> {
+        for index in E::indexes() {
+            self.add_to_index(index, entity, key)?;
+        }
+        Ok(())
+    }
+
+    fn add_to_index<E: Entity>(&self, index: &Index, entity: &E, key: &[u8]) -> ErebusResult<()> {
+        let Some(value) = entity.index_value(index) else {
+            return Ok(());
+        };
+        let name = E::index_table_name(index);
+        let mut table = self.txn.open_multimap_table(index_table_def(&name))?;
+        let index_key = self.data_key.keyed_hash(&name, value.as_bytes());
+        table.insert(index_key.as_slice(), key)?;
+        Ok(())
+    }
+
+    fn remove_from_indexes<E: Entity>(&self, entity: &E, key: &[u8]) -> ErebusResult<()> {
+        for index in E::indexes() {
+            let Some(value) = entity.index_value(index) else {
+                continue;
+            };
+            let name = E::index_table_name(index);
+            let mut table = self.txn.open_multimap_table(index_table_def(&name))?;
+            let index_key = self.data_key.keyed_hash(&name, value.as_bytes());
+            table.remove(index_key.as_slice(), key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Index tables map keyed hashes of the indexed values to the keys of the entities.
+pub(super) fn index_table_def(name: &str) -> MultimapTableDefinition<'_, StoredKey, StoredKey> {
+    MultimapTableDefinition::new(name)
+}
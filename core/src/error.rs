@@ -6,14 +6,53 @@ pub type ErebusResult<T> = Result<T, ErebusError>;
 pub enum ErebusError {
     #[error("Invalid invite code")]
     InvalidInviteCode,
+    #[error("Invalid username")]
+    InvalidUsername,
+    #[error("Username is already taken")]
+    UsernameTaken,
+    #[error("User already exists")]
+    UserAlreadyExists,
+    #[error("Unknown user")]
+    UnknownUser,
+    #[error("User is suspended: {reason}")]
+    UserSuspended {
+        reason: String,
+        expires_at: Option<u64>,
+    },
     #[error("Lost connection to the context thread")]
     ContextDisconnected,
+    #[error("Connection timed out")]
+    ConnectionTimedOut,
+    #[error("Connection closed")]
+    ConnectionClosed,
+    #[error("Client is not keeping up with its messages")]
+    SlowConsumer,
     #[error("Encryption error")]
     Encryption,
     #[error("Decryption error")]
     Decryption,
+    #[error("Unique index {0} already contains the value")]
+    UniqueIndexViolation(&'static str),
+    #[error("Unsupported {0} in stored record")]
+    UnsupportedRecord(&'static str),
+    #[error("Unsupported database schema: {0}")]
+    UnsupportedSchema(String),
     #[error("Database password error")]
     DatabasePassword,
+    #[error("{0} database migrations have to run first")]
+    PendingMigrations(usize),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Admin socket is in use by another server")]
+    AdminSocketInUse,
+    #[error("Admin request failed: {0}")]
+    AdminRequestFailed(String),
+    #[error("The server is not running")]
+    ServerNotRunning,
+    #[error("Invalid server name")]
+    InvalidServerName,
+    #[error("No certificate found in TLS certificate file")]
+    TlsCertificateMissing,
     #[error("Client error: {0}")]
     Client(#[from] crate::client::error::ErebusClientError),
     #[error("Base64 decode error: {0}")]
@@ -32,6 +71,12 @@ pub enum ErebusError {
     Decode(#[from] bincode::error::DecodeError),
     #[error("Encode error: {0}")]
     Encode(#[from] bincode::error::EncodeError),
+    #[cfg(feature = "tls")]
+    #[error("TLS error: {0}")]
+    Tls(#[from] tokio_rustls::rustls::Error),
+    #[cfg(feature = "tls")]
+    #[error("TLS certificate error: {0}")]
+    TlsCertificate(#[from] tokio_rustls::rustls::pki_types::pem::Error),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Deserialization error: {0}")]
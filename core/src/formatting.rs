@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 pub fn format_byte_size(bytes: usize) -> String {
     if bytes < 1_000 {
         format!("{} B", bytes)
@@ -9,3 +11,24 @@ pub fn format_byte_size(bytes: usize) -> String {
         format!("{} GB", bytes / 1_000_000_000)
     }
 }
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds < 60 {
+        format!("{}s", seconds)
+    } else if seconds < 3600 {
+        format!("{}m {}s", seconds / 60, seconds % 60)
+    } else if seconds < 86400 {
+        format!("{}h {}m", seconds / 3600, seconds % 3600 / 60)
+    } else {
+        format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600)
+    }
+}
+
+pub fn format_fingerprint(bytes: &[u8]) -> String {
+    bytes
+        .chunks(2)
+        .map(|chunk| chunk.iter().map(|b| format!("{b:02X}")).collect::<String>())
+        .collect::<Vec<_>>()
+        .join(":")
+}
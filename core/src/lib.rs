@@ -5,3 +5,4 @@ pub mod error;
 pub mod formatting;
 pub mod message;
 pub mod server;
+pub mod transport;
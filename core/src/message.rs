@@ -4,6 +4,9 @@ use bincode::{Decode, Encode};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::debug;
 
+/// Version of the wire protocol, announced by the client during the identity handshake.
+pub const PROTOCOL_VERSION: u16 = 1;
+
 pub struct Message {
     length: u32,
     data: Vec<u8>,
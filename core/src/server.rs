@@ -1,12 +1,36 @@
-use crate::crypto::sha256_bytes;
 use crate::error::ErebusResult;
+#[cfg(unix)]
+use crate::server::admin::socket::AdminSocket;
+use crate::server::config::ServerConfig;
 use crate::server::connection_handler::ConnectionHandler;
 use crate::server::socket_id::SocketId;
 use crate::server::state::ErebusServerState;
+#[cfg(feature = "tls")]
+use crate::transport::tls::ServerTlsConfig;
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tracing::info;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::Poll;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
 
+/// How long to wait before accepting client connections again after it failed.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// How long to wait before accepting on the admin socket again after it failed.
+#[cfg(unix)]
+const ADMIN_ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// How long a client may take to complete the TLS handshake.
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(feature = "server")]
+pub mod admin;
+#[cfg(feature = "server")]
+pub mod config;
 #[cfg(feature = "server")]
 mod connection;
 #[cfg(feature = "server")]
@@ -15,6 +39,12 @@ mod connection_handler;
 mod entities;
 pub mod message;
 #[cfg(feature = "server")]
+pub mod migrations;
+#[cfg(feature = "server")]
+mod proof_of_work;
+#[cfg(feature = "server")]
+mod rate_limit;
+#[cfg(feature = "server")]
 mod services;
 #[cfg(feature = "server")]
 pub mod socket_id;
@@ -24,35 +54,213 @@ pub mod state;
 #[cfg(feature = "server")]
 pub struct ErebusServer {
     state: Arc<ErebusServerState>,
-    listener: TcpListener,
+    listeners: Vec<TcpListener>,
+    /// Listener to poll first on the next accept, rotating so none of them is starved.
+    next_listener: AtomicUsize,
     connection_handler: ConnectionHandler,
+    #[cfg(unix)]
+    admin_socket: Option<AdminSocket>,
+    #[cfg(feature = "tls")]
+    tls_acceptor: Option<TlsAcceptor>,
 }
 
 #[cfg(feature = "server")]
 impl ErebusServer {
-    pub async fn bind(server_port: impl AsRef<str>) -> ErebusResult<Self> {
-        let state = ErebusServerState::new()?;
+    /// Opens the state and binds a listener on every configured address, enabling TLS and
+    /// the proof-of-work gate if the config says so.
+    pub async fn bind(config: &ServerConfig) -> ErebusResult<Self> {
+        let state = ErebusServerState::new(config)?;
         info!("State initialized");
 
-        let address = format!("{}:{}", "0.0.0.0", server_port.as_ref());
-        let listener = TcpListener::bind(address).await?;
-        info!("TCP listener bound");
+        let mut listeners = Vec::with_capacity(config.bind_addresses.len());
+        for address in &config.bind_addresses {
+            listeners.push(TcpListener::bind((*address, config.port)).await?);
+        }
+        info!("TCP listeners bound");
+
+        #[cfg(unix)]
+        let admin_socket = if config.admin.enabled {
+            Some(AdminSocket::bind(&config.admin_socket_path())?)
+        } else {
+            None
+        };
 
-        Ok(Self {
+        let server = Self {
             state: Arc::new(state),
-            listener,
-            connection_handler: ConnectionHandler::new(),
+            listeners,
+            next_listener: AtomicUsize::new(0),
+            connection_handler: ConnectionHandler::new(config.limits.clone()),
+            #[cfg(unix)]
+            admin_socket,
+            #[cfg(feature = "tls")]
+            tls_acceptor: None,
+        };
+
+        #[cfg(feature = "tls")]
+        let server = match config.tls_config() {
+            Some(tls) => server.with_tls(&tls)?,
+            None => server,
+        };
+
+        Ok(if config.proof_of_work.enabled {
+            server.with_proof_of_work(config.proof_of_work.difficulty)
+        } else {
+            server
         })
     }
 
+    /// Wraps every accepted stream in TLS using the given certificate and key.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, config: &ServerTlsConfig) -> ErebusResult<Self> {
+        self.tls_acceptor = Some(config.acceptor()?);
+        info!("TLS enabled");
+        Ok(self)
+    }
+
+    /// Demands a proof of work of at least `difficulty` bits before each registration
+    /// challenge, rising automatically while many registrations are attempted.
+    pub fn with_proof_of_work(mut self, difficulty: u8) -> Self {
+        self.connection_handler = self.connection_handler.with_proof_of_work(difficulty);
+        info!("Proof of work required for registration, difficulty {difficulty}");
+        self
+    }
+
     pub async fn run(&self) -> ErebusResult<()> {
-        info!("Listening on {}", self.listener.local_addr()?);
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Accepts connections until the given future resolves, e.g. on a shutdown signal.
+    /// Follow up with [`Self::shutdown`] to close the open connections.
+    pub async fn run_until(&self, shutdown: impl Future<Output = ()>) -> ErebusResult<()> {
+        for listener in &self.listeners {
+            info!("Listening on {}", listener.local_addr()?);
+        }
+        tokio::pin!(shutdown);
         loop {
-            let (stream, addr) = self.listener.accept().await?;
+            let (stream, addr) = tokio::select! {
+                accepted = self.next_connection() => match accepted {
+                    Some(accepted) => accepted,
+                    None => continue,
+                },
+                _ = self.accept_admin() => continue,
+                _ = &mut shutdown => {
+                    info!("Stopped accepting connections");
+                    return Ok(());
+                }
+            };
             let socket_id = SocketId::from(addr);
             info!("Connected to {}", socket_id);
+            let Some(slot) = self.connection_handler.admit(socket_id, addr.ip()) else {
+                continue;
+            };
+
+            #[cfg(feature = "tls")]
+            if let Some(acceptor) = self.tls_acceptor.clone() {
+                let state = self.state.clone();
+                let connection_handler = self.connection_handler.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+                    {
+                        Ok(Ok(stream)) => {
+                            connection_handler.handle(state, stream, socket_id, addr, slot)
+                        }
+                        Ok(Err(e)) => warn!("TLS handshake with {} failed: {}", socket_id, e),
+                        Err(_) => warn!("TLS handshake with {} timed out", socket_id),
+                    }
+                });
+                continue;
+            }
+
             self.connection_handler
-                .handle(self.state.clone(), stream, socket_id);
+                .handle(self.state.clone(), stream, socket_id, addr, slot);
         }
     }
+
+    /// Accepts the next client connection. Errors are logged rather than stopping the server,
+    /// and resolve to `None` after a short pause.
+    async fn next_connection(&self) -> Option<(TcpStream, SocketAddr)> {
+        match self.accept().await {
+            Ok(accepted) => Some(accepted),
+            Err(e) => {
+                warn!("Failed to accept a connection: {e}");
+                // Errors like running out of file descriptors last a while, don't spin on them
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                None
+            }
+        }
+    }
+
+    /// Accepts the next connection on whichever listener gets one first. Like
+    /// `tokio::select!`, each call starts polling at a different listener, so a busy one can't
+    /// keep the others from being accepted.
+    async fn accept(&self) -> std::io::Result<(TcpStream, SocketAddr)> {
+        let start = self.next_listener.fetch_add(1, Ordering::Relaxed);
+        std::future::poll_fn(|cx| {
+            let count = self.listeners.len();
+            for offset in 0..count {
+                let listener = &self.listeners[(start + offset) % count];
+                if let Poll::Ready(accepted) = listener.poll_accept(cx) {
+                    return Poll::Ready(accepted);
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Accepts the next connection on the admin socket and serves it on its own task. Errors
+    /// are logged rather than stopping the server. Never resolves if there is no admin socket.
+    #[cfg(unix)]
+    async fn accept_admin(&self) {
+        let Some(admin_socket) = &self.admin_socket else {
+            return std::future::pending().await;
+        };
+
+        match admin_socket.accept().await {
+            Ok(stream) => {
+                tokio::spawn(admin::socket::serve(
+                    stream,
+                    self.state.clone(),
+                    self.connection_handler.clone(),
+                ));
+            }
+            Err(e) => {
+                warn!("Failed to accept an admin connection: {e}");
+                // Errors like running out of file descriptors last a while, don't spin on them
+                tokio::time::sleep(ADMIN_ACCEPT_RETRY_DELAY).await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn accept_admin(&self) {
+        std::future::pending().await
+    }
+
+    /// Notifies all clients, gives their connections up to `grace_period` to finish the
+    /// messages they are handling and then closes the database.
+    pub async fn shutdown(self, grace_period: Duration) {
+        info!("Shutting down");
+        drop(self.listeners);
+        #[cfg(unix)]
+        drop(self.admin_socket);
+        self.connection_handler.shutdown(grace_period).await;
+
+        match Arc::try_unwrap(self.state) {
+            Ok(state) => {
+                drop(state);
+                info!("Database closed");
+            }
+            Err(_) => warn!("Connections are still running, the database closes once they end"),
+        }
+    }
+}
+
+/// Seconds since the unix epoch, how the server stores and reports points in time.
+#[cfg(feature = "server")]
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
 }
@@ -0,0 +1,172 @@
+use crate::database::entity::Entity;
+use crate::error::ErebusResult;
+use crate::server::connection_handler::ConnectionHandler;
+use crate::server::entities::user::User;
+use crate::server::message::error::ErebusServerError;
+use crate::server::message::ServerMessage;
+use crate::server::state::ErebusServerState;
+use bincode::{Decode, Encode};
+
+#[cfg(unix)]
+pub mod client;
+#[cfg(unix)]
+pub(crate) mod socket;
+
+/// Requests the server CLI sends over the admin socket.
+#[derive(Encode, Decode)]
+pub enum AdminRequest {
+    InviteGenerate {
+        count: u16,
+    },
+    InviteList,
+    IdentityFingerprint,
+    ConnectionList,
+    /// Closes the connection with the given base64 id.
+    ConnectionKick {
+        id: String,
+    },
+    UserList {
+        offset: u32,
+        limit: u32,
+    },
+    /// Users are addressed by id or username.
+    UserShow {
+        user: String,
+    },
+    UserSuspend {
+        user: String,
+        reason: String,
+        /// Unix timestamp in seconds, `None` to suspend indefinitely.
+        expires_at: Option<u64>,
+    },
+    UserUnsuspend {
+        user: String,
+    },
+    UserDelete {
+        user: String,
+    },
+}
+
+#[derive(Encode, Decode)]
+pub enum AdminResponse {
+    InviteCodes(Vec<String>),
+    Fingerprint(String),
+    Connections(Vec<ConnectionInfo>),
+    /// Whether the connection to kick existed.
+    Kicked(bool),
+    Users {
+        users: Vec<UserInfo>,
+        total: u64,
+    },
+    User(UserInfo),
+    Error(String),
+}
+
+/// Snapshot of a live connection.
+#[derive(Encode, Decode)]
+pub struct ConnectionInfo {
+    pub id: String,
+    pub peer_address: String,
+    /// Unix timestamp in seconds.
+    pub connected_since: u64,
+    pub user_id: Option<String>,
+    pub username: Option<String>,
+    /// `None` until the client finished the identity handshake.
+    pub protocol_version: Option<u16>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub messages_in: u64,
+    pub messages_out: u64,
+}
+
+#[derive(Encode, Decode)]
+pub struct UserInfo {
+    pub id: String,
+    pub username: String,
+    /// Unix timestamp in seconds.
+    pub created_at: u64,
+    pub suspension: Option<SuspensionInfo>,
+}
+
+#[derive(Encode, Decode)]
+pub struct SuspensionInfo {
+    pub reason: String,
+    /// Unix timestamp in seconds.
+    pub suspended_at: u64,
+    /// Unix timestamp in seconds, `None` if the suspension doesn't expire.
+    pub expires_at: Option<u64>,
+    /// Whether the suspension is in effect, expired suspensions are kept until lifted.
+    pub active: bool,
+}
+
+impl From<&User> for UserInfo {
+    fn from(user: &User) -> Self {
+        let active = user.active_suspension().is_some();
+        Self {
+            id: user.public_key.as_base64(),
+            username: user.username.clone(),
+            created_at: user.created_at,
+            suspension: user.suspension.as_ref().map(|suspension| SuspensionInfo {
+                reason: suspension.reason.clone(),
+                suspended_at: suspension.suspended_at,
+                expires_at: suspension.expires_at,
+                active,
+            }),
+        }
+    }
+}
+
+pub(crate) fn handle_request(
+    state: &ErebusServerState,
+    connections: &ConnectionHandler,
+    request: AdminRequest,
+) -> ErebusResult<AdminResponse> {
+    match request {
+        AdminRequest::InviteGenerate { count } => {
+            let codes = (0..count)
+                .map(|_| state.invite_generate())
+                .collect::<ErebusResult<_>>()?;
+            Ok(AdminResponse::InviteCodes(codes))
+        }
+        AdminRequest::InviteList => Ok(AdminResponse::InviteCodes(state.invite_list()?)),
+        AdminRequest::IdentityFingerprint => {
+            Ok(AdminResponse::Fingerprint(state.identity_fingerprint()))
+        }
+        AdminRequest::ConnectionList => Ok(AdminResponse::Connections(connections.list())),
+        AdminRequest::ConnectionKick { id } => Ok(AdminResponse::Kicked(connections.kick(&id))),
+        AdminRequest::UserList { offset, limit } => Ok(AdminResponse::Users {
+            users: state
+                .user_list(offset as usize, limit as usize)?
+                .iter()
+                .map(UserInfo::from)
+                .collect(),
+            total: state.user_count()?,
+        }),
+        AdminRequest::UserShow { user } => {
+            Ok(AdminResponse::User((&state.user_resolve(&user)?).into()))
+        }
+        AdminRequest::UserSuspend {
+            user,
+            reason,
+            expires_at,
+        } => {
+            let user_id = state.user_resolve(&user)?.id();
+            let user = state.user_suspend(&user_id, reason.clone(), expires_at)?;
+            let suspended = ErebusServerError::UserSuspended { reason, expires_at };
+            connections.kick_user(&user_id, ServerMessage::Error(suspended));
+            Ok(AdminResponse::User((&user).into()))
+        }
+        AdminRequest::UserUnsuspend { user } => {
+            let user_id = state.user_resolve(&user)?.id();
+            Ok(AdminResponse::User(
+                (&state.user_unsuspend(&user_id)?).into(),
+            ))
+        }
+        AdminRequest::UserDelete { user } => {
+            let user = state.user_resolve(&user)?;
+            state.user_delete(&user.id())?;
+            connections.kick_user(&user.id(), ServerMessage::Kicked);
+            Ok(AdminResponse::User((&user).into()))
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::{ErebusError, ErebusResult};
+use crate::message::{MessageRecv, MessageSend};
+use crate::server::admin::{AdminRequest, AdminResponse, ConnectionInfo, UserInfo};
+use crate::server::config::ServerConfig;
+use std::io::ErrorKind;
+use tokio::net::UnixStream;
+
+/// Blocking client for the admin socket of a running server.
+pub struct AdminClient {
+    runtime: tokio::runtime::Runtime,
+    stream: UnixStream,
+}
+
+impl AdminClient {
+    /// Connects to the admin socket, or returns `None` if no server is listening on it.
+    pub fn connect(config: &ServerConfig) -> ErebusResult<Option<Self>> {
+        if !config.admin.enabled {
+            return Ok(None);
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        match runtime.block_on(UnixStream::connect(config.admin_socket_path())) {
+            Ok(stream) => Ok(Some(Self { runtime, stream })),
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn request(&mut self, request: AdminRequest) -> ErebusResult<AdminResponse> {
+        let stream = &mut self.stream;
+        let response = self.runtime.block_on(async move {
+            request.send(stream).await?;
+            AdminResponse::recv(stream).await
+        })?;
+
+        match response {
+            AdminResponse::Error(error) => Err(ErebusError::AdminRequestFailed(error)),
+            response => Ok(response),
+        }
+    }
+
+    pub fn invite_generate(&mut self, count: u16) -> ErebusResult<Vec<String>> {
+        match self.request(AdminRequest::InviteGenerate { count })? {
+            AdminResponse::InviteCodes(codes) => Ok(codes),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    pub fn invite_list(&mut self) -> ErebusResult<Vec<String>> {
+        match self.request(AdminRequest::InviteList)? {
+            AdminResponse::InviteCodes(codes) => Ok(codes),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    pub fn identity_fingerprint(&mut self) -> ErebusResult<String> {
+        match self.request(AdminRequest::IdentityFingerprint)? {
+            AdminResponse::Fingerprint(fingerprint) => Ok(fingerprint),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    pub fn connection_list(&mut self) -> ErebusResult<Vec<ConnectionInfo>> {
+        match self.request(AdminRequest::ConnectionList)? {
+            AdminResponse::Connections(connections) => Ok(connections),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    /// Returns whether a connection with the id existed.
+    pub fn connection_kick(&mut self, id: impl AsRef<str>) -> ErebusResult<bool> {
+        let id = id.as_ref().to_string();
+        match self.request(AdminRequest::ConnectionKick { id })? {
+            AdminResponse::Kicked(kicked) => Ok(kicked),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    /// Returns a page of users and the total number of users.
+    pub fn user_list(&mut self, offset: u32, limit: u32) -> ErebusResult<(Vec<UserInfo>, u64)> {
+        match self.request(AdminRequest::UserList { offset, limit })? {
+            AdminResponse::Users { users, total } => Ok((users, total)),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    pub fn user_show(&mut self, user: &str) -> ErebusResult<UserInfo> {
+        let user = user.to_string();
+        self.user_request(AdminRequest::UserShow { user })
+    }
+
+    pub fn user_suspend(
+        &mut self,
+        user: &str,
+        reason: &str,
+        expires_at: Option<u64>,
+    ) -> ErebusResult<UserInfo> {
+        self.user_request(AdminRequest::UserSuspend {
+            user: user.to_string(),
+            reason: reason.to_string(),
+            expires_at,
+        })
+    }
+
+    pub fn user_unsuspend(&mut self, user: &str) -> ErebusResult<UserInfo> {
+        let user = user.to_string();
+        self.user_request(AdminRequest::UserUnsuspend { user })
+    }
+
+    pub fn user_delete(&mut self, user: &str) -> ErebusResult<UserInfo> {
+        let user = user.to_string();
+        self.user_request(AdminRequest::UserDelete { user })
+    }
+
+    fn user_request(&mut self, request: AdminRequest) -> ErebusResult<UserInfo> {
+        match self.request(request)? {
+            AdminResponse::User(user) => Ok(user),
+            _ => Err(unexpected_response()),
+        }
+    }
+}
+
+fn unexpected_response() -> ErebusError {
+    ErebusError::AdminRequestFailed("unexpected response".to_string())
+}
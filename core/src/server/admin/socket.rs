@@ -0,0 +1,102 @@
+use crate::error::{ErebusError, ErebusResult};
+use crate::message::{MessageRecv, MessageSend};
+use crate::server::admin::{handle_request, AdminRequest, AdminResponse};
+use crate::server::connection_handler::ConnectionHandler;
+use crate::server::state::ErebusServerState;
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info, warn};
+
+/// Unix socket for the server CLI. Only the owner of the server process may connect, which
+/// is what authenticates admin requests.
+pub struct AdminSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl AdminSocket {
+    pub fn bind(path: &Path) -> ErebusResult<Self> {
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(ErebusError::AdminSocketInUse);
+            }
+            // Left behind by a server which didn't shut down cleanly
+            std::fs::remove_file(path)?;
+        }
+
+        // Bound inside a directory only the owner can enter and moved into place once its
+        // permissions are set, so no one else can connect in between.
+        let staging = staging_dir(path);
+        let _ = std::fs::remove_dir_all(&staging);
+        std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+        let staged_path = staging.join("admin.sock");
+        let listener = UnixListener::bind(&staged_path).and_then(|listener| {
+            std::fs::set_permissions(&staged_path, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged_path, path)?;
+            Ok(listener)
+        });
+        let _ = std::fs::remove_dir_all(&staging);
+        let listener = listener?;
+        info!("Admin socket bound at {}", path.display());
+
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub async fn accept(&self) -> std::io::Result<UnixStream> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(stream)
+    }
+}
+
+/// Private directory next to the socket path, on the same file system so the socket can be
+/// renamed out of it.
+fn staging_dir(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{file_name}.{}", std::process::id()))
+}
+
+impl Drop for AdminSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Answers requests on an admin connection until the CLI hangs up.
+pub async fn serve(
+    mut stream: UnixStream,
+    state: Arc<ErebusServerState>,
+    connections: ConnectionHandler,
+) {
+    debug!("Admin connected");
+    loop {
+        let request = match AdminRequest::recv(&mut stream).await {
+            Ok(request) => request,
+            Err(ErebusError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                warn!("Failed to read admin request: {e}");
+                break;
+            }
+        };
+
+        // Requests write to the database and can take a while, e.g. generating many invites
+        let state = state.clone();
+        let connections = connections.clone();
+        let response =
+            tokio::task::spawn_blocking(move || handle_request(&state, &connections, request))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.map_err(|e| e.to_string()))
+                .unwrap_or_else(AdminResponse::Error);
+        if let Err(e) = response.send(&mut stream).await {
+            warn!("Failed to answer admin request: {e}");
+            break;
+        }
+    }
+    debug!("Admin disconnected");
+}
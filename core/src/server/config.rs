@@ -0,0 +1,492 @@
+use crate::crypto::password::KdfCost;
+use crate::crypto::proof_of_work::WorkChallenge;
+use crate::error::{ErebusError, ErebusResult};
+use crate::server::proof_of_work::MAX_LOAD_DIFFICULTY;
+use crate::server::rate_limit::RateLimit;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Used when `EREBUS_CONFIG` doesn't point to a config file.
+const DEFAULT_CONFIG_PATH: &str = "./erebus.toml";
+/// Base proof-of-work difficulty which stays within what clients solve once load is added.
+const MAX_PROOF_OF_WORK_DIFFICULTY: u8 = WorkChallenge::MAX_DIFFICULTY - MAX_LOAD_DIFFICULTY;
+
+/// Configuration shared by the server and the server CLI, read from a TOML file and
+/// overridden by `EREBUS_*` environment variables.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_addresses: Vec<IpAddr>,
+    pub port: u16,
+    /// Directory holding the database.
+    pub data_dir: PathBuf,
+    /// Default tracing filter, `RUST_LOG` still takes precedence.
+    pub log_level: String,
+    pub shutdown_grace_period_secs: u64,
+    pub limits: LimitsConfig,
+    pub proof_of_work: ProofOfWorkConfig,
+    pub tls: TlsConfig,
+    pub admin: AdminConfig,
+    pub database: DatabaseConfig,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_connections_per_ip: usize,
+    /// Unauthenticated requests a single connection may make at once.
+    pub connection_request_burst: u32,
+    /// Milliseconds until a connection regains a single request.
+    pub connection_request_refill_ms: u64,
+    /// Unauthenticated requests all connections of one IP may make at once, opening a
+    /// connection counts as one.
+    pub peer_request_burst: u32,
+    /// Milliseconds until an IP regains a single request.
+    pub peer_request_refill_ms: u64,
+    /// Messages which may wait for a connection before its overflow policy applies.
+    pub outbound_queue_size: usize,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProofOfWorkConfig {
+    pub enabled: bool,
+    /// Base difficulty in bits, raised automatically under load.
+    pub difficulty: u8,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+}
+
+/// Local control socket used by the server CLI while the server is running (unix only).
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub enabled: bool,
+    /// Defaults to `admin.sock` in the data directory.
+    pub socket_path: Option<PathBuf>,
+}
+
+/// Argon2id cost of deriving the database key from `DATABASE_PASSWORD`. Only applies when a
+/// key is derived anew, existing databases keep the parameters stored in them.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub kdf_memory_kib: u32,
+    pub kdf_iterations: u32,
+    pub kdf_parallelism: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_addresses: vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)],
+            port: 58469,
+            data_dir: PathBuf::from("./data"),
+            log_level: if cfg!(debug_assertions) {
+                "trace".to_string()
+            } else {
+                "info".to_string()
+            },
+            shutdown_grace_period_secs: 8,
+            limits: LimitsConfig::default(),
+            proof_of_work: ProofOfWorkConfig::default(),
+            tls: TlsConfig::default(),
+            admin: AdminConfig::default(),
+            database: DatabaseConfig::default(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        let cost = KdfCost::default();
+        Self {
+            kdf_memory_kib: cost.memory_kib,
+            kdf_iterations: cost.iterations,
+            kdf_parallelism: cost.parallelism,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_connections_per_ip: 16,
+            connection_request_burst: 10,
+            connection_request_refill_ms: 1000,
+            peer_request_burst: 30,
+            peer_request_refill_ms: 2000,
+            outbound_queue_size: 256,
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            socket_path: None,
+        }
+    }
+}
+
+impl Default for ProofOfWorkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            difficulty: 18,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads the config file at `EREBUS_CONFIG` (or `./erebus.toml`, if it exists), applies
+    /// the environment overrides and validates the result.
+    pub fn load() -> ErebusResult<Self> {
+        let path = std::env::var("EREBUS_CONFIG").ok().map(PathBuf::from);
+        let mut config = match &path {
+            Some(path) => Self::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::read(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_env(|key| std::env::var(key).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> ErebusResult<Self> {
+        let content = std::fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .map_err(|e| ErebusError::InvalidConfig(format!("{}: {}", path.display(), e.message())))
+    }
+
+    /// Overrides settings with the `EREBUS_*` variables `env` has a value for.
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> ErebusResult<()> {
+        let env = &env;
+        if let Some(addresses) = env("EREBUS_BIND_ADDRESSES") {
+            self.bind_addresses = addresses
+                .split(',')
+                .map(|address| parse_env("EREBUS_BIND_ADDRESSES", address.trim()))
+                .collect::<ErebusResult<_>>()?;
+        }
+        env_override(env, "EREBUS_PORT", &mut self.port)?;
+        env_override(env, "EREBUS_DATA_DIR", &mut self.data_dir)?;
+        env_override(env, "EREBUS_LOG_LEVEL", &mut self.log_level)?;
+        env_override(
+            env,
+            "EREBUS_MAX_CONNECTIONS_PER_IP",
+            &mut self.limits.max_connections_per_ip,
+        )?;
+        env_override(env, "EREBUS_POW_ENABLED", &mut self.proof_of_work.enabled)?;
+        env_override(
+            env,
+            "EREBUS_POW_DIFFICULTY",
+            &mut self.proof_of_work.difficulty,
+        )?;
+        env_override(env, "EREBUS_TLS_ENABLED", &mut self.tls.enabled)?;
+        if let Some(path) = env("EREBUS_TLS_CERT_PATH") {
+            self.tls.cert_path = Some(path.into());
+        }
+        if let Some(path) = env("EREBUS_TLS_KEY_PATH") {
+            self.tls.key_path = Some(path.into());
+        }
+        env_override(env, "EREBUS_ADMIN_ENABLED", &mut self.admin.enabled)?;
+        if let Some(path) = env("EREBUS_ADMIN_SOCKET_PATH") {
+            self.admin.socket_path = Some(path.into());
+        }
+        env_override(
+            env,
+            "EREBUS_DB_KDF_MEMORY_KIB",
+            &mut self.database.kdf_memory_kib,
+        )?;
+        env_override(
+            env,
+            "EREBUS_DB_KDF_ITERATIONS",
+            &mut self.database.kdf_iterations,
+        )?;
+        env_override(
+            env,
+            "EREBUS_DB_KDF_PARALLELISM",
+            &mut self.database.kdf_parallelism,
+        )?;
+        Ok(())
+    }
+
+    fn validate(&self) -> ErebusResult<()> {
+        let invalid = |message: &str| Err(ErebusError::InvalidConfig(message.to_string()));
+
+        if self.bind_addresses.is_empty() {
+            return invalid("at least one bind address is required");
+        }
+        if self.log_level.trim().is_empty() {
+            return invalid("log_level must not be empty");
+        }
+
+        let limits = &self.limits;
+        if limits.max_connections_per_ip == 0
+            || limits.connection_request_burst == 0
+            || limits.connection_request_refill_ms == 0
+            || limits.peer_request_burst == 0
+            || limits.peer_request_refill_ms == 0
+            || limits.outbound_queue_size == 0
+        {
+            return invalid("limits must be greater than zero");
+        }
+
+        if self.proof_of_work.enabled
+            && self.proof_of_work.difficulty > MAX_PROOF_OF_WORK_DIFFICULTY
+        {
+            return invalid(&format!(
+                "proof_of_work.difficulty must be at most {MAX_PROOF_OF_WORK_DIFFICULTY}, load adds \
+                 up to {MAX_LOAD_DIFFICULTY} bits"
+            ));
+        }
+
+        if self.tls.enabled {
+            if !cfg!(feature = "tls") {
+                return invalid("tls is enabled, but the server was built without TLS support");
+            }
+            if self.tls.cert_path.is_none() || self.tls.key_path.is_none() {
+                return invalid("tls.cert_path and tls.key_path are required when tls is enabled");
+            }
+        }
+
+        if !self.database.kdf_cost().is_valid() {
+            return invalid("database.kdf_* are not valid Argon2 parameters");
+        }
+
+        Ok(())
+    }
+
+    pub fn database_path(&self) -> PathBuf {
+        self.data_dir.join("server.db")
+    }
+
+    pub fn admin_socket_path(&self) -> PathBuf {
+        self.admin
+            .socket_path
+            .clone()
+            .unwrap_or_else(|| self.data_dir.join("admin.sock"))
+    }
+
+    pub fn shutdown_grace_period(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_period_secs)
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls_config(&self) -> Option<crate::transport::tls::ServerTlsConfig> {
+        if !self.tls.enabled {
+            return None;
+        }
+
+        Some(crate::transport::tls::ServerTlsConfig {
+            cert_path: self.tls.cert_path.clone()?,
+            key_path: self.tls.key_path.clone()?,
+        })
+    }
+}
+
+impl DatabaseConfig {
+    pub fn kdf_cost(&self) -> KdfCost {
+        KdfCost {
+            memory_kib: self.kdf_memory_kib,
+            iterations: self.kdf_iterations,
+            parallelism: self.kdf_parallelism,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub(crate) fn connection_rate_limit(&self) -> RateLimit {
+        RateLimit::new(
+            self.connection_request_burst,
+            Duration::from_millis(self.connection_request_refill_ms),
+        )
+    }
+
+    pub(crate) fn peer_rate_limit(&self) -> RateLimit {
+        RateLimit::new(
+            self.peer_request_burst,
+            Duration::from_millis(self.peer_request_refill_ms),
+        )
+    }
+}
+
+fn env_override<T: FromStr>(
+    env: impl Fn(&str) -> Option<String>,
+    key: &str,
+    target: &mut T,
+) -> ErebusResult<()> {
+    if let Some(value) = env(key) {
+        *target = parse_env(key, &value)?;
+    }
+    Ok(())
+}
+
+fn parse_env<T: FromStr>(key: &str, value: &str) -> ErebusResult<T> {
+    value
+        .parse()
+        .map_err(|_| ErebusError::InvalidConfig(format!("{key} has an invalid value: {value}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(toml: &str) -> ServerConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn with_env(mut config: ServerConfig, vars: &[(&str, &str)]) -> ErebusResult<ServerConfig> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        config.apply_env(|key| vars.get(key).map(|value| value.to_string()))?;
+        Ok(config)
+    }
+
+    fn assert_rejected(config: ServerConfig, field: &str) {
+        match config.validate() {
+            Err(ErebusError::InvalidConfig(message)) => assert!(
+                message.contains(field),
+                "expected an error about {field}, got: {message}"
+            ),
+            Err(e) => panic!("expected an invalid config error, got: {e}"),
+            Ok(()) => panic!("expected {field} to be rejected"),
+        }
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        let config = ServerConfig::default();
+        assert!(config.validate().is_ok());
+        assert!(parse("").validate().is_ok());
+    }
+
+    #[test]
+    fn empty_bind_addresses_are_rejected() {
+        assert_rejected(parse("bind_addresses = []"), "bind address");
+    }
+
+    #[test]
+    fn empty_log_level_is_rejected() {
+        assert_rejected(parse("log_level = ' '"), "log_level");
+    }
+
+    #[test]
+    fn zero_limits_are_rejected() {
+        for field in [
+            "max_connections_per_ip",
+            "connection_request_burst",
+            "connection_request_refill_ms",
+            "peer_request_burst",
+            "peer_request_refill_ms",
+            "outbound_queue_size",
+        ] {
+            assert_rejected(parse(&format!("[limits]\n{field} = 0")), "limits");
+        }
+    }
+
+    #[test]
+    fn too_high_proof_of_work_difficulty_is_rejected() {
+        let max = MAX_PROOF_OF_WORK_DIFFICULTY;
+        let enabled = |difficulty: u8| {
+            parse(&format!(
+                "[proof_of_work]\nenabled = true\ndifficulty = {difficulty}"
+            ))
+        };
+
+        assert!(enabled(max).validate().is_ok());
+        assert_rejected(enabled(max + 1), &format!("at most {max}"));
+        // Only checked when it is used
+        let disabled = parse(&format!("[proof_of_work]\ndifficulty = {}", max + 1));
+        assert!(disabled.validate().is_ok());
+    }
+
+    #[test]
+    fn tls_without_certificate_is_rejected() {
+        assert_rejected(parse("[tls]\nenabled = true"), "tls");
+    }
+
+    #[test]
+    fn invalid_kdf_cost_is_rejected() {
+        assert_rejected(parse("[database]\nkdf_memory_kib = 0"), "database.kdf_");
+        assert_rejected(parse("[database]\nkdf_parallelism = 0"), "database.kdf_");
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(toml::from_str::<ServerConfig>("prot = 1").is_err());
+        assert!(toml::from_str::<ServerConfig>("[limits]\nmax_connections = 1").is_err());
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let file = parse(
+            "port = 1000\n\
+             data_dir = 'file'\n\
+             [limits]\n\
+             max_connections_per_ip = 4\n\
+             [proof_of_work]\n\
+             enabled = true",
+        );
+        let config = with_env(
+            file,
+            &[
+                ("EREBUS_PORT", "2000"),
+                ("EREBUS_BIND_ADDRESSES", "127.0.0.1, ::1"),
+                ("EREBUS_POW_ENABLED", "false"),
+                ("EREBUS_ADMIN_SOCKET_PATH", "/run/erebus.sock"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 2000);
+        assert_eq!(
+            config.bind_addresses,
+            [
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ]
+        );
+        assert!(!config.proof_of_work.enabled);
+        assert_eq!(config.admin_socket_path(), Path::new("/run/erebus.sock"));
+        // Settings without a variable keep the file's value
+        assert_eq!(config.data_dir, Path::new("file"));
+        assert_eq!(config.limits.max_connections_per_ip, 4);
+    }
+
+    #[test]
+    fn invalid_environment_values_are_rejected() {
+        for (key, value) in [
+            ("EREBUS_PORT", "70000"),
+            ("EREBUS_BIND_ADDRESSES", "127.0.0.1,localhost"),
+            ("EREBUS_POW_ENABLED", "yes"),
+        ] {
+            match with_env(ServerConfig::default(), &[(key, value)]) {
+                Err(ErebusError::InvalidConfig(message)) => assert!(message.contains(key)),
+                _ => panic!("expected {key}={value} to be rejected"),
+            }
+        }
+    }
+
+    #[test]
+    fn environment_values_are_validated() {
+        let config = with_env(
+            ServerConfig::default(),
+            &[("EREBUS_MAX_CONNECTIONS_PER_IP", "0")],
+        );
+        assert_rejected(config.unwrap(), "limits");
+    }
+}
@@ -1,86 +1,403 @@
 use crate::client::message::ClientMessage;
-use crate::crypto::registration_challenge::RegistrationChallengeWithCode;
-use crate::error::ErebusResult;
-use crate::message::{MessageRecv, MessageSend};
-use crate::server::connection_handler::ConnectionHandler;
-use crate::server::message::error::{ErebusServerError, ErebusServerResult};
+use crate::crypto::private_key::PrivateKey;
+use crate::crypto::public_key::PublicKey;
+use crate::crypto::registration_challenge::{RegistrationChallenge, RegistrationChallengeWithCode};
+use crate::database::entity::Entity;
+use crate::error::{ErebusError, ErebusResult};
+use crate::message::MessageRecv;
+use crate::server::admin::ConnectionInfo;
+use crate::server::connection::outbound::{Outbound, OverflowPolicy};
+use crate::server::connection::session::Session;
+use crate::server::connection::stats::{ConnectionStats, Counted};
+use crate::server::connection_handler::{ConnectionHandler, ConnectionSlot};
 use crate::server::message::ServerMessage;
+use crate::server::message::error::{ErebusServerError, ErebusServerResult};
+use crate::server::proof_of_work::PendingWork;
+use crate::server::rate_limit::TokenBucket;
 use crate::server::socket_id::SocketId;
 use crate::server::state::ErebusServerState;
-use std::sync::Arc;
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tracing::{debug, info};
+use crate::server::unix_now;
+use crate::transport::{TransportReader, TransportWriter};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{Notify, mpsc, watch};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+mod outbound;
+mod session;
+mod stats;
+
+/// How often the server pings each client.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a client may stay silent before its socket is removed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+/// How long the writer may take to send what is left once a connection closes.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Connection {
     id: SocketId,
+    peer_address: SocketAddr,
     state: Arc<ErebusServerState>,
-    writer: Arc<Mutex<OwnedWriteHalf>>,
+    outbound: mpsc::Sender<Outbound>,
+    /// Notified when a message had to be refused because the client doesn't keep up.
+    overflowed: Notify,
+    /// Notified when the server closes the connection, e.g. when an admin kicks it.
+    closed: Notify,
     connections: ConnectionHandler,
+    connected_at: Instant,
+    /// Unix timestamp of the connect, for admins.
+    connected_since: u64,
+    /// Key of this connection's identity handshake, binding the server's proof to it.
+    ephemeral: PrivateKey,
+    /// Announced by the client during the identity handshake.
+    protocol_version: OnceLock<u16>,
+    stats: Arc<ConnectionStats>,
+    last_seen: std::sync::Mutex<Instant>,
+    session: std::sync::Mutex<Session>,
+    rate_limit: std::sync::Mutex<TokenBucket>,
+    /// Registration challenge held back until the client proves its work.
+    pending_work: PendingWork<RegistrationChallengeWithCode>,
+    shutdown: watch::Receiver<bool>,
+    /// Held for as long as the connection exists.
+    _slot: ConnectionSlot,
 }
 
 impl Connection {
-    pub fn spawn(
+    pub fn spawn<S>(
         state: Arc<ErebusServerState>,
         connections: ConnectionHandler,
-        stream: TcpStream,
+        shutdown: watch::Receiver<bool>,
+        stream: S,
         id: SocketId,
-    ) -> Arc<Self> {
-        let (reader, writer) = stream.into_split();
+        peer_address: SocketAddr,
+        slot: ConnectionSlot,
+    ) where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let stats = Arc::new(ConnectionStats::default());
+        let (reader, writer) = crate::transport::split(stream);
+        let reader: TransportReader = Box::new(Counted::new(reader, stats.bytes_in.clone()));
+        let writer: TransportWriter = Box::new(Counted::new(writer, stats.bytes_out.clone()));
+
+        let (outbound, outbound_receiver) = mpsc::channel(connections.limits().outbound_queue_size);
+        let rate_limit = connections.limits().connection_rate_limit().bucket();
+        let mut writer_task = tokio::spawn(outbound::write_messages(
+            writer,
+            outbound_receiver,
+            stats.clone(),
+        ));
 
         let connection = Arc::new(Self {
             state,
             id,
+            peer_address,
             connections,
-            writer: Arc::new(Mutex::new(writer)),
+            outbound,
+            overflowed: Notify::new(),
+            closed: Notify::new(),
+            connected_at: Instant::now(),
+            connected_since: unix_now(),
+            ephemeral: PrivateKey::generate(),
+            protocol_version: OnceLock::new(),
+            stats,
+            last_seen: std::sync::Mutex::new(Instant::now()),
+            session: std::sync::Mutex::new(Session::default()),
+            rate_limit: std::sync::Mutex::new(rate_limit),
+            pending_work: PendingWork::default(),
+            shutdown,
+            _slot: slot,
         });
 
-        let connection_clone = connection.clone();
+        connection.connections.insert(connection.clone());
+        let connection_clone = connection;
         tokio::spawn(async move {
-            let result = connection_clone.listen(reader).await;
-            if let Err(e) = result {
+            let result = tokio::select! {
+                result = connection_clone.listen(reader) => result,
+                result = connection_clone.heartbeat() => result,
+                _ = connection_clone.overflowed.notified() => Err(ErebusError::SlowConsumer),
+                _ = connection_clone.closed.notified() => {
+                    info!("Kicking connection {}", id);
+                    Ok(())
+                }
+            };
+            if let Err(e) = &result {
                 info!("Lost connection {}: {}", id, e);
             } else {
-                info!("Lost connection {}", id);
+                info!("Closed connection {}", id);
             }
 
-            connection_clone.connections.remove(connection_clone.id);
-        });
+            if let Some(user_id) = connection_clone.user_id() {
+                debug!("User {user_id} went offline");
+            }
 
-        connection
+            // Let the writer send what is still queued, unless the connection is broken
+            if result.is_ok() {
+                let flushed = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                    let _ = connection_clone.outbound.send(Outbound::Close).await;
+                    let _ = (&mut writer_task).await;
+                })
+                .await;
+                if flushed.is_err() {
+                    debug!("Connection {} did not flush in time", id);
+                }
+            }
+            writer_task.abort();
+
+            // Release the connection (and with it the server state) before announcing the
+            // removal, so a shutdown waiting on it can close the database right after.
+            let connections = connection_clone.connections.clone();
+            drop(connection_clone);
+            connections.remove(id);
+        });
     }
 
-    async fn listen(&self, mut reader: OwnedReadHalf) -> ErebusResult<()> {
+    async fn listen(&self, mut reader: TransportReader) -> ErebusResult<()> {
         debug!("Connection {} is listening", self.id);
+        self.send_message(ServerMessage::Identity {
+            identity: self.state.identity_public_key().clone(),
+            ephemeral: self.ephemeral.public_key(),
+        })?;
+
+        let mut shutdown = self.shutdown.clone();
         loop {
-            let message = ClientMessage::recv(&mut reader).await?;
+            // Only waiting for the next message is interrupted by a shutdown, a message which
+            // already arrived is always handled to completion.
+            let message = tokio::select! {
+                message = ClientMessage::recv(&mut reader) => message?,
+                _ = async { let _ = shutdown.wait_for(|shutdown| *shutdown).await; } => {
+                    // The connection handler already queued the notice for the client
+                    debug!("Closing connection {} for shutdown", self.id);
+                    return Ok(());
+                }
+            };
+            *self.last_seen.lock().unwrap() = Instant::now();
+            self.stats.messages_in.fetch_add(1, Ordering::Relaxed);
+            if let ClientMessage::Disconnect = message {
+                debug!("Connection {} is disconnecting", self.id);
+                return Ok(());
+            }
             if let Err(error) = self.handle_message(message).await {
-                self.send_message(ServerMessage::Error(error)).await?;
+                self.send_message(ServerMessage::Error(error))?;
             }
         }
     }
 
-    async fn send_message(&self, message: ServerMessage) -> ErebusResult<()> {
-        let mut writer = self.writer.lock().await;
-        message.send(&mut *writer).await?;
-        Ok(())
+    /// Pings the client periodically and fails once it has been silent for too long.
+    async fn heartbeat(&self) -> ErebusResult<()> {
+        let mut interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + HEARTBEAT_INTERVAL,
+            HEARTBEAT_INTERVAL,
+        );
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if self.last_seen.lock().unwrap().elapsed() > IDLE_TIMEOUT {
+                return Err(ErebusError::ConnectionTimedOut);
+            }
+            self.queue_message(ServerMessage::Ping(self.heartbeat_nonce()));
+        }
+    }
+
+    /// Milliseconds since the connection was accepted, echoed back by the client in a pong.
+    fn heartbeat_nonce(&self) -> u64 {
+        self.connected_at.elapsed().as_millis() as u64
+    }
+
+    pub fn id(&self) -> SocketId {
+        self.id
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.peer_address.ip()
+    }
+
+    /// Closes the connection after sending what is already queued for it.
+    pub fn close(&self) {
+        self.closed.notify_one();
+    }
+
+    pub fn info(&self) -> ConnectionInfo {
+        let session = self.session.lock().unwrap();
+        ConnectionInfo {
+            id: self.id.as_base64(),
+            peer_address: self.peer_address.to_string(),
+            connected_since: self.connected_since,
+            user_id: session.user_id().map(str::to_string),
+            username: session.username().map(str::to_string),
+            protocol_version: self.protocol_version.get().copied(),
+            bytes_in: self.stats.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.stats.bytes_out.load(Ordering::Relaxed),
+            messages_in: self.stats.messages_in.load(Ordering::Relaxed),
+            messages_out: self.stats.messages_out.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn user_id(&self) -> Option<String> {
+        self.session.lock().unwrap().user_id().map(str::to_string)
+    }
+
+    /// Sends a reply to the client. Replies go through the same queue and [`OverflowPolicy`]
+    /// as everything else, so a client which doesn't read them is disconnected rather than
+    /// holding up the connection.
+    fn send_message(&self, message: ServerMessage) -> ErebusResult<()> {
+        self.enqueue(message).map(|_| ())
+    }
+
+    /// Queues a message without waiting, applying its [`OverflowPolicy`] if the queue is full.
+    /// Returns whether the message was queued.
+    pub fn queue_message(&self, message: ServerMessage) -> bool {
+        matches!(self.enqueue(message), Ok(true))
+    }
+
+    /// Returns whether the message was queued, or `false` if it was dropped. Fails if the
+    /// connection is closed or is being closed for not keeping up.
+    fn enqueue(&self, message: ServerMessage) -> ErebusResult<bool> {
+        let policy = OverflowPolicy::of(&message);
+        match self.outbound.try_send(Outbound::Message(message)) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Closed(_)) => Err(ErebusError::ConnectionClosed),
+            Err(TrySendError::Full(_)) => match policy {
+                OverflowPolicy::Drop => {
+                    debug!("Dropped a message for slow connection {}", self.id);
+                    Ok(false)
+                }
+                OverflowPolicy::Disconnect => {
+                    warn!("Disconnecting slow connection {}", self.id);
+                    self.overflowed.notify_one();
+                    Err(ErebusError::SlowConsumer)
+                }
+            },
+        }
+    }
+
+    /// Requests anyone can make without logging in, each costs a database lookup or crypto.
+    fn is_rate_limited(message: &ClientMessage) -> bool {
+        matches!(
+            message,
+            ClientMessage::IdentityChallenge { .. }
+                | ClientMessage::RegisterChallenge(_)
+                | ClientMessage::ProofOfWork(_)
+                | ClientMessage::Register { .. }
+                | ClientMessage::LoginRequest(_)
+                | ClientMessage::LoginResponse(_)
+        )
+    }
+
+    /// Takes a request from both the connection's and its IP's budget.
+    fn take_request(&self) -> ErebusServerResult<()> {
+        let result = self
+            .rate_limit
+            .lock()
+            .unwrap()
+            .try_take()
+            .and_then(|()| self.connections.take_peer_request(self.ip()));
+
+        result.map_err(|retry_after| {
+            debug!("Rate limited connection {}", self.id);
+            ErebusServerError::RateLimited { retry_after }
+        })
     }
 
     async fn handle_message(&self, message: ClientMessage) -> ErebusServerResult<()> {
+        if Self::is_rate_limited(&message) {
+            self.take_request()?;
+        }
+
         match message {
+            ClientMessage::Ping(nonce) => {
+                self.send_message(ServerMessage::Pong(nonce))?;
+            }
+            ClientMessage::Pong(nonce) => {
+                let round_trip = self.heartbeat_nonce().saturating_sub(nonce);
+                debug!("Connection {} round-trip time: {round_trip}ms", self.id);
+            }
+            ClientMessage::IdentityChallenge {
+                protocol_version,
+                ephemeral,
+            } => {
+                self.handle_identity_challenge(protocol_version, ephemeral)
+                    .await?;
+            }
             ClientMessage::RegisterChallenge(challenge_and_code) => {
                 self.handle_register_challenge(challenge_and_code).await?;
             }
+            ClientMessage::ProofOfWork(counter) => {
+                self.handle_proof_of_work(counter).await?;
+            }
+            ClientMessage::Register {
+                invite_code,
+                username,
+                public_key,
+            } => {
+                self.handle_register(invite_code, username, public_key)
+                    .await?;
+            }
+            ClientMessage::LoginRequest(public_key) => {
+                self.handle_login_request(public_key).await?;
+            }
+            ClientMessage::LoginResponse(solved_challenge) => {
+                self.handle_login_response(solved_challenge).await?;
+            }
+            ClientMessage::Logout => self.handle_logout().await?,
+            ClientMessage::Disconnect => {}
         }
         Ok(())
     }
 }
 
 impl Connection {
+    async fn handle_identity_challenge(
+        &self,
+        protocol_version: u16,
+        client_ephemeral: PublicKey,
+    ) -> ErebusServerResult<()> {
+        debug!(
+            "Received identity challenge from {} speaking protocol version {protocol_version}",
+            self.id
+        );
+        let _ = self.protocol_version.set(protocol_version);
+        let proof = self
+            .state
+            .identity_prove(&self.ephemeral, &client_ephemeral, protocol_version);
+        self.send_message(ServerMessage::IdentityProof(proof))?;
+        Ok(())
+    }
+
     async fn handle_register_challenge(
         &self,
         challenge_and_code: RegistrationChallengeWithCode,
+    ) -> ErebusServerResult<()> {
+        let Some(gate) = self.connections.proof_of_work() else {
+            return self.solve_register_challenge(challenge_and_code).await;
+        };
+
+        let work_challenge = gate.issue();
+        debug!(
+            "Demanding proof of work with difficulty {} from {}",
+            work_challenge.difficulty, self.id
+        );
+        self.pending_work.hold(work_challenge.clone(), challenge_and_code);
+        self.send_message(ServerMessage::WorkChallenge(work_challenge))?;
+        Ok(())
+    }
+
+    async fn handle_proof_of_work(&self, counter: u64) -> ErebusServerResult<()> {
+        let Some(challenge_and_code) = self.pending_work.redeem(counter) else {
+            return Err(ErebusServerError::InvalidProofOfWork);
+        };
+
+        self.solve_register_challenge(challenge_and_code).await
+    }
+
+    async fn solve_register_challenge(
+        &self,
+        challenge_and_code: RegistrationChallengeWithCode,
     ) -> ErebusServerResult<()> {
         let code_string = challenge_and_code.invite_code.as_base64();
         debug!(
@@ -92,10 +409,95 @@ impl Connection {
             return Err(ErebusServerError::InvalidInviteCode);
         };
         let solved_challenge = challenge_and_code.challenge.decrypt(&code.verify)?;
+        *self.session.lock().unwrap() = Session::RegistrationAllowed {
+            invite_code: challenge_and_code.invite_code,
+        };
+
+        self.send_message(ServerMessage::RegisterChallengeSolved(solved_challenge))?;
+
+        Ok(())
+    }
 
-        self.send_message(ServerMessage::RegisterChallengeSolved(solved_challenge))
-            .await?;
+    async fn handle_register(
+        &self,
+        invite_code: PublicKey,
+        username: String,
+        public_key: PublicKey,
+    ) -> ErebusServerResult<()> {
+        debug!("Received registration from {} for {username}", self.id);
+        let session = std::mem::take(&mut *self.session.lock().unwrap());
+        let Session::RegistrationAllowed {
+            invite_code: allowed_code,
+        } = session
+        else {
+            return Err(ErebusServerError::RegistrationChallengeRequired);
+        };
+        if allowed_code != invite_code {
+            return Err(ErebusServerError::RegistrationChallengeRequired);
+        }
+
+        let user = self
+            .state
+            .user_register(&invite_code, username, public_key)?;
+        info!("Registered user {} ({})", user.username, user.id());
+
+        self.send_message(ServerMessage::Registered)?;
+        Ok(())
+    }
+
+    async fn handle_login_request(&self, public_key: PublicKey) -> ErebusServerResult<()> {
+        let user_id = public_key.as_base64();
+        debug!("Received login request from {} for {user_id}", self.id);
+
+        let user = self.state.user_find_for_login(&user_id)?;
+
+        let original_challenge = RegistrationChallenge::generate();
+        let challenge = original_challenge.encrypt(&user.public_key)?;
+        *self.session.lock().unwrap() = Session::LoginPending {
+            user_id,
+            original_challenge,
+        };
+
+        self.send_message(ServerMessage::LoginChallenge(challenge))?;
+        Ok(())
+    }
+
+    async fn handle_login_response(
+        &self,
+        solved_challenge: RegistrationChallenge,
+    ) -> ErebusServerResult<()> {
+        let session = std::mem::take(&mut *self.session.lock().unwrap());
+        let Session::LoginPending {
+            user_id,
+            original_challenge,
+        } = session
+        else {
+            return Err(ErebusServerError::LoginFailed);
+        };
+
+        if !original_challenge.verify(&solved_challenge) {
+            return Err(ErebusServerError::LoginFailed);
+        }
+        let user = self.state.user_find_for_login(&user_id)?;
+
+        info!("Connection {} logged in as {}", self.id, user.username);
+        *self.session.lock().unwrap() = Session::Authenticated {
+            user_id,
+            username: user.username.clone(),
+        };
+        self.send_message(ServerMessage::LoginSuccess {
+            username: user.username,
+        })?;
+        Ok(())
+    }
+
+    async fn handle_logout(&self) -> ErebusServerResult<()> {
+        let session = std::mem::take(&mut *self.session.lock().unwrap());
+        if let Some(user_id) = session.user_id() {
+            info!("Connection {} logged out of {user_id}", self.id);
+        }
 
+        self.send_message(ServerMessage::LoggedOut)?;
         Ok(())
     }
 }
@@ -0,0 +1,82 @@
+use crate::error::ErebusResult;
+use crate::message::MessageSend;
+use crate::server::connection::stats::ConnectionStats;
+use crate::server::message::ServerMessage;
+use crate::transport::TransportWriter;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+pub enum Outbound {
+    Message(ServerMessage),
+    /// Sent after the last message, the writer closes the connection once it gets here.
+    Close,
+}
+
+/// What to do with a message when the outbound queue of a connection is full.
+pub enum OverflowPolicy {
+    /// Skip the message, it is superseded soon anyway.
+    Drop,
+    /// The client can't keep up, close its connection.
+    Disconnect,
+}
+
+impl OverflowPolicy {
+    pub fn of(message: &ServerMessage) -> Self {
+        if is_presence(message) {
+            Self::Drop
+        } else {
+            Self::Disconnect
+        }
+    }
+}
+
+/// Presence messages only tell the client that the server is still there. Missing one costs
+/// nothing, the next heartbeat carries the same news, so they are dropped under load. Every
+/// other message changes what the client knows and is critical.
+fn is_presence(message: &ServerMessage) -> bool {
+    matches!(message, ServerMessage::Ping(_) | ServerMessage::Pong(_))
+}
+
+/// Owns the write half of a connection and sends everything queued for it in order.
+pub async fn write_messages(
+    mut writer: TransportWriter,
+    mut receiver: mpsc::Receiver<Outbound>,
+    stats: Arc<ConnectionStats>,
+) -> ErebusResult<()> {
+    while let Some(outbound) = receiver.recv().await {
+        match outbound {
+            Outbound::Message(message) => {
+                message.send(&mut writer).await?;
+                stats.messages_out.fetch_add(1, Ordering::Relaxed);
+            }
+            Outbound::Close => break,
+        }
+    }
+
+    writer.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_presence_messages_are_dropped() {
+        for message in [ServerMessage::Ping(1), ServerMessage::Pong(1)] {
+            assert!(matches!(OverflowPolicy::of(&message), OverflowPolicy::Drop));
+        }
+        for message in [
+            ServerMessage::Registered,
+            ServerMessage::Kicked,
+            ServerMessage::ShuttingDown,
+        ] {
+            assert!(matches!(
+                OverflowPolicy::of(&message),
+                OverflowPolicy::Disconnect
+            ));
+        }
+    }
+}
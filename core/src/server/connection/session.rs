@@ -0,0 +1,38 @@
+use crate::crypto::public_key::PublicKey;
+use crate::crypto::registration_challenge::RegistrationChallenge;
+
+/// Authentication state of a single connection.
+#[derive(Default)]
+pub enum Session {
+    #[default]
+    Anonymous,
+    /// The server solved the registration challenge for the invite code, after the client
+    /// proved its work if that was demanded. Allows a single registration attempt with it.
+    RegistrationAllowed {
+        invite_code: PublicKey,
+    },
+    LoginPending {
+        user_id: String,
+        original_challenge: RegistrationChallenge,
+    },
+    Authenticated {
+        user_id: String,
+        username: String,
+    },
+}
+
+impl Session {
+    pub fn user_id(&self) -> Option<&str> {
+        match self {
+            Self::Authenticated { user_id, .. } => Some(user_id),
+            _ => None,
+        }
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            Self::Authenticated { username, .. } => Some(username),
+            _ => None,
+        }
+    }
+}
@@ -1,25 +1,117 @@
+use crate::server::admin::ConnectionInfo;
+use crate::server::config::LimitsConfig;
 use crate::server::connection::Connection;
+use crate::server::message::ServerMessage;
+use crate::server::proof_of_work::ProofOfWorkGate;
+use crate::server::rate_limit::IpRateLimiter;
 use crate::server::socket_id::SocketId;
 use crate::server::state::ErebusServerState;
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::net::TcpStream;
-use tracing::debug;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{watch, Notify};
+use tracing::{debug, info, warn};
 
 #[derive(Clone)]
 pub struct ConnectionHandler {
     connections: Arc<DashMap<SocketId, Arc<Connection>>>,
+    limits: Arc<LimitsConfig>,
+    connections_per_ip: Arc<DashMap<IpAddr, usize>>,
+    peer_rate_limiter: Arc<IpRateLimiter>,
+    proof_of_work: Option<Arc<ProofOfWorkGate>>,
+    shutdown: Arc<watch::Sender<bool>>,
+    /// Notified whenever a connection was removed.
+    removed: Arc<Notify>,
 }
 
 impl ConnectionHandler {
-    pub fn new() -> Self {
+    pub fn new(limits: LimitsConfig) -> Self {
         Self {
             connections: Arc::new(DashMap::new()),
+            peer_rate_limiter: Arc::new(IpRateLimiter::new(limits.peer_rate_limit())),
+            limits: Arc::new(limits),
+            connections_per_ip: Arc::new(DashMap::new()),
+            proof_of_work: None,
+            shutdown: Arc::new(watch::Sender::new(false)),
+            removed: Arc::new(Notify::new()),
         }
     }
 
-    pub fn handle(&self, state: Arc<ErebusServerState>, stream: TcpStream, id: SocketId) {
-        let connection = Connection::spawn(state, self.clone(), stream, id);
+    pub fn with_proof_of_work(mut self, difficulty: u8) -> Self {
+        self.proof_of_work = Some(Arc::new(ProofOfWorkGate::new(difficulty)));
+        self
+    }
+
+    pub fn limits(&self) -> &LimitsConfig {
+        &self.limits
+    }
+
+    /// The proof-of-work gate for registrations, if this deployment uses one.
+    pub fn proof_of_work(&self) -> Option<&ProofOfWorkGate> {
+        self.proof_of_work.as_deref()
+    }
+
+    /// Checks whether the peer may open another connection and reserves its place in the
+    /// per-IP limit, before any work like a TLS handshake is done for it.
+    pub fn admit(&self, id: SocketId, ip: IpAddr) -> Option<ConnectionSlot> {
+        if *self.shutdown.borrow() {
+            debug!("Dropped connection {} during shutdown", id);
+            return None;
+        }
+        if self.take_peer_request(ip).is_err() {
+            warn!("Refused connection {}, its IP is rate limited", id);
+            return None;
+        }
+
+        let mut count = self.connections_per_ip.entry(ip).or_default();
+        if *count >= self.limits.max_connections_per_ip {
+            warn!(
+                "Refused connection {}, too many connections from its IP",
+                id
+            );
+            return None;
+        }
+        *count += 1;
+
+        Some(ConnectionSlot {
+            ip,
+            connections_per_ip: self.connections_per_ip.clone(),
+        })
+    }
+
+    pub fn handle<S>(
+        &self,
+        state: Arc<ErebusServerState>,
+        stream: S,
+        id: SocketId,
+        peer_address: SocketAddr,
+        slot: ConnectionSlot,
+    ) where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        if *self.shutdown.borrow() {
+            debug!("Dropped connection {} during shutdown", id);
+            return;
+        }
+
+        Connection::spawn(
+            state,
+            self.clone(),
+            self.shutdown.subscribe(),
+            stream,
+            id,
+            peer_address,
+            slot,
+        );
+    }
+
+    /// Called by a connection before its task starts, so that the task's [`Self::remove`]
+    /// always finds it.
+    pub fn insert(&self, connection: Arc<Connection>) {
+        let id = connection.id();
         self.connections.insert(id, connection);
         debug!("Added connection {}", id);
     }
@@ -27,5 +119,164 @@ impl ConnectionHandler {
     pub fn remove(&self, id: SocketId) {
         self.connections.remove(&id);
         debug!("Removed connection {}", id);
+        self.removed.notify_waiters();
+    }
+
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        self.connections
+            .iter()
+            .map(|connection| connection.info())
+            .collect()
+    }
+
+    /// Kicks the connection with the given base64 id, returns whether it exists.
+    pub fn kick(&self, id: &str) -> bool {
+        let socket = self
+            .connections
+            .iter()
+            .find(|connection| connection.id().as_base64() == id)
+            .map(|connection| connection.id());
+
+        let Some(socket) = socket else {
+            return false;
+        };
+        self.send_to(socket, ServerMessage::Kicked);
+        if let Some(connection) = self.connections.get(&socket) {
+            connection.close();
+        }
+        true
+    }
+
+    /// Kicks every connection logged in as the user after sending them `message`, returns
+    /// how many were kicked.
+    pub fn kick_user(&self, user_id: &str, message: ServerMessage) -> usize {
+        self.send_to_user(user_id, message);
+
+        let connections = self
+            .connections
+            .iter()
+            .filter(|connection| connection.user_id().as_deref() == Some(user_id))
+            .map(|connection| connection.value().clone())
+            .collect::<Vec<_>>();
+        for connection in &connections {
+            connection.close();
+        }
+        connections.len()
+    }
+
+    /// Takes a request from the budget shared by all connections of the IP, or returns how
+    /// long to wait until the next request is allowed.
+    pub fn take_peer_request(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.peer_rate_limiter.try_take(ip)
+    }
+
+    /// Queues a message for a single connection, returns whether it was queued.
+    pub fn send_to(&self, socket: SocketId, message: ServerMessage) -> bool {
+        self.connections
+            .get(&socket)
+            .is_some_and(|connection| connection.queue_message(message))
+    }
+
+    /// Queues a message for every connection the user is logged in on, returns how many
+    /// connections it was queued for.
+    pub fn send_to_user(&self, user_id: &str, message: ServerMessage) -> usize {
+        self.connections
+            .iter()
+            .filter(|connection| connection.user_id().as_deref() == Some(user_id))
+            .filter(|connection| connection.queue_message(message.clone()))
+            .count()
+    }
+
+    /// Queues a message for every connection, returns how many connections it was queued for.
+    pub fn broadcast(&self, message: ServerMessage) -> usize {
+        self.connections
+            .iter()
+            .filter(|connection| connection.queue_message(message.clone()))
+            .count()
+    }
+
+    /// Tells every connection to finish its current message and close, then waits up to
+    /// `grace_period` for all of them to be removed.
+    pub async fn shutdown(&self, grace_period: Duration) {
+        self.shutdown.send_replace(true);
+        self.broadcast(ServerMessage::ShuttingDown);
+        info!("Closing {} connection(s)", self.connections.len());
+
+        let deadline = tokio::time::Instant::now() + grace_period;
+        loop {
+            let removed = self.removed.notified();
+            tokio::pin!(removed);
+            removed.as_mut().enable();
+
+            if self.connections.is_empty() {
+                info!("All connections closed");
+                return;
+            }
+            if tokio::time::timeout_at(deadline, removed).await.is_err() {
+                warn!(
+                    "{} connection(s) did not close in time",
+                    self.connections.len()
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// A connection's place in the per-IP limit, given back when dropped.
+pub struct ConnectionSlot {
+    ip: IpAddr,
+    connections_per_ip: Arc<DashMap<IpAddr, usize>>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        if let Entry::Occupied(mut count) = self.connections_per_ip.entry(self.ip) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn admit(handler: &ConnectionHandler, ip: IpAddr, port: u16) -> Option<ConnectionSlot> {
+        handler.admit(SocketId::from(SocketAddr::new(ip, port)), ip)
+    }
+
+    #[test]
+    fn connections_per_ip_are_capped() {
+        let handler = ConnectionHandler::new(LimitsConfig {
+            max_connections_per_ip: 2,
+            ..LimitsConfig::default()
+        });
+        let peer = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+
+        let first = admit(&handler, peer, 1).unwrap();
+        let _second = admit(&handler, peer, 2).unwrap();
+        assert!(admit(&handler, peer, 3).is_none());
+        assert!(admit(&handler, IpAddr::from(Ipv4Addr::new(192, 0, 2, 2)), 1).is_some());
+
+        // Closing a connection frees its place
+        drop(first);
+        assert!(admit(&handler, peer, 4).is_some());
+    }
+
+    #[test]
+    fn rate_limited_peers_are_refused() {
+        let handler = ConnectionHandler::new(LimitsConfig {
+            peer_request_burst: 1,
+            peer_request_refill_ms: 60_000,
+            ..LimitsConfig::default()
+        });
+        let peer = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+
+        let _slot = admit(&handler, peer, 1).unwrap();
+        assert!(admit(&handler, peer, 2).is_none());
     }
 }
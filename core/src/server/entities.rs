@@ -1 +1,3 @@
 pub mod invite_code;
+pub mod server_identity;
+pub mod user;
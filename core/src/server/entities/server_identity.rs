@@ -0,0 +1,33 @@
+use crate::crypto::private_key::PrivateKey;
+use crate::crypto::public_key::PublicKey;
+use crate::database::entity::Entity;
+use serde::{Deserialize, Serialize};
+
+/// Long-term keypair the server uses to prove its identity to clients.
+#[derive(Serialize, Deserialize)]
+pub struct ServerIdentity {
+    pub public: PublicKey,
+    pub private: PrivateKey,
+}
+
+impl Entity for ServerIdentity {
+    type Id = String;
+
+    fn id(&self) -> Self::Id {
+        Self::ID.to_string()
+    }
+
+    fn table_name() -> &'static str {
+        "server_identity"
+    }
+}
+
+impl ServerIdentity {
+    pub const ID: &'static str = "identity";
+
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub fn generate() -> Self {
+        let (public, private) = crate::crypto::x25519_keypair();
+        Self { public, private }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::crypto::public_key::PublicKey;
+use crate::database::entity::{Entity, Index};
+use crate::server::unix_now;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct User {
+    pub public_key: PublicKey,
+    pub username: String,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    #[serde(default)]
+    pub suspension: Option<Suspension>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Suspension {
+    pub reason: String,
+    /// Unix timestamp in seconds
+    pub suspended_at: u64,
+    /// Unix timestamp in seconds, `None` if the suspension doesn't expire.
+    pub expires_at: Option<u64>,
+}
+
+impl Entity for User {
+    type Id = String;
+
+    fn id(&self) -> Self::Id {
+        self.public_key.as_base64()
+    }
+
+    fn table_name() -> &'static str {
+        "users"
+    }
+
+    fn indexes() -> &'static [Index] {
+        &[Self::USERNAME_INDEX]
+    }
+
+    fn index_value(&self, index: &Index) -> Option<String> {
+        match index.name {
+            "username" => Some(self.username.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl User {
+    pub const USERNAME_MIN_LENGTH: usize = 3;
+    pub const USERNAME_MAX_LENGTH: usize = 32;
+    pub const USERNAME_INDEX: Index = Index::unique("username");
+
+    pub fn new(public_key: PublicKey, username: String) -> Self {
+        Self {
+            public_key,
+            username,
+            created_at: unix_now(),
+            suspension: None,
+        }
+    }
+
+    /// The suspension of the user, unless there is none or it expired.
+    pub fn active_suspension(&self) -> Option<&Suspension> {
+        self.suspension.as_ref().filter(|suspension| {
+            suspension
+                .expires_at
+                .is_none_or(|expires_at| expires_at > unix_now())
+        })
+    }
+
+    pub fn is_valid_username(username: &str) -> bool {
+        (Self::USERNAME_MIN_LENGTH..=Self::USERNAME_MAX_LENGTH).contains(&username.len())
+            && username
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+}
+
+impl Suspension {
+    pub fn new(reason: String, expires_at: Option<u64>) -> Self {
+        Self {
+            reason,
+            suspended_at: unix_now(),
+            expires_at,
+        }
+    }
+}
@@ -1,10 +1,35 @@
+use crate::crypto::identity_proof::IdentityProof;
+use crate::crypto::proof_of_work::WorkChallenge;
+use crate::crypto::public_key::PublicKey;
 use crate::crypto::registration_challenge::RegistrationChallenge;
 use bincode::{Decode, Encode};
 
 pub mod error;
 
-#[derive(Encode, Decode)]
+#[derive(Clone, Encode, Decode)]
 pub enum ServerMessage {
     Error(error::ErebusServerError),
+    /// Sent right after a client connects, carrying the server's long-term identity key and a
+    /// key generated for this connection only.
+    Identity {
+        identity: PublicKey,
+        ephemeral: PublicKey,
+    },
+    IdentityProof(IdentityProof),
+    Ping(u64),
+    Pong(u64),
+    /// The server wants a proof of work before it processes the registration challenge.
+    WorkChallenge(WorkChallenge),
     RegisterChallengeSolved(RegistrationChallenge),
+    Registered,
+    /// A challenge encrypted to the user's public key, which only the account owner can solve.
+    LoginChallenge(RegistrationChallenge),
+    LoginSuccess {
+        username: String,
+    },
+    LoggedOut,
+    /// An admin closed the connection.
+    Kicked,
+    /// The server is shutting down and closes the connection, clients should reconnect later.
+    ShuttingDown,
 }
@@ -1,12 +1,35 @@
 use crate::error::ErebusError;
 use bincode::{Decode, Encode};
+use std::time::Duration;
 
 pub type ErebusServerResult<T> = Result<T, ErebusServerError>;
 
-#[derive(Debug, thiserror::Error, Encode, Decode)]
+#[derive(Debug, Clone, thiserror::Error, Encode, Decode)]
 pub enum ErebusServerError {
     #[error("Invalid invite code")]
     InvalidInviteCode,
+    #[error("Invalid username")]
+    InvalidUsername,
+    #[error("Username is already taken")]
+    UsernameTaken,
+    #[error("User already exists")]
+    UserAlreadyExists,
+    #[error("Unknown user")]
+    UnknownUser,
+    #[error("User is suspended: {reason}")]
+    UserSuspended {
+        reason: String,
+        /// Unix timestamp in seconds, `None` if the suspension doesn't expire.
+        expires_at: Option<u64>,
+    },
+    #[error("Login failed")]
+    LoginFailed,
+    #[error("Invalid proof of work")]
+    InvalidProofOfWork,
+    #[error("Registration requires a solved registration challenge for the invite code")]
+    RegistrationChallengeRequired,
+    #[error("Too many requests, retry in {retry_after:?}")]
+    RateLimited { retry_after: Duration },
     #[error("Unexpected error")]
     Unexpected,
 }
@@ -15,6 +38,13 @@ impl From<ErebusError> for ErebusServerError {
     fn from(error: ErebusError) -> Self {
         match error {
             ErebusError::InvalidInviteCode => Self::InvalidInviteCode,
+            ErebusError::InvalidUsername => Self::InvalidUsername,
+            ErebusError::UsernameTaken => Self::UsernameTaken,
+            ErebusError::UserAlreadyExists => Self::UserAlreadyExists,
+            ErebusError::UnknownUser => Self::UnknownUser,
+            ErebusError::UserSuspended { reason, expires_at } => {
+                Self::UserSuspended { reason, expires_at }
+            }
             _ => Self::Unexpected,
         }
     }
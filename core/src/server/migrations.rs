@@ -0,0 +1,61 @@
+use crate::database::Database;
+use crate::database::migration::{Migrations, UpgradePlan};
+use crate::error::ErebusResult;
+use crate::server::config::ServerConfig;
+use crate::server::entities::invite_code::InviteCode;
+use crate::server::entities::server_identity::ServerIdentity;
+use crate::server::entities::user::User;
+use zeroize::Zeroizing;
+
+/// Every stored entity of the server and the steps upgrading them. Add a step here whenever
+/// an entity's `VERSION` is bumped.
+pub fn migrations() -> Migrations {
+    Migrations::new()
+        .entity::<InviteCode>()
+        .entity::<ServerIdentity>()
+        .entity::<User>()
+}
+
+/// What the next start of the server would change in the database, found without writing to
+/// it.
+pub fn pending(config: &ServerConfig) -> ErebusResult<UpgradePlan> {
+    if !config.database_path().exists() {
+        return Ok(UpgradePlan::default());
+    }
+    Database::plan(&config.database_path(), &migrations())
+}
+
+/// Upgrades the database and runs the pending migrations without starting the server.
+pub fn run(config: &ServerConfig) -> ErebusResult<UpgradePlan> {
+    if !config.database_path().exists() {
+        return Ok(UpgradePlan::default());
+    }
+    let migrations = migrations();
+    let format = Database::plan(&config.database_path(), &migrations)?.format;
+    let db = Database::open(
+        &config.database_path(),
+        config.database.kdf_cost(),
+        &migrations,
+    )?;
+    Ok(UpgradePlan {
+        format,
+        migrations: db.migrate(&migrations)?,
+    })
+}
+
+/// Changes the database password to `new_password`, see [`Database::rotate_password`]. The
+/// server has to be stopped, and started with the new `DATABASE_PASSWORD` afterwards.
+pub fn rotate_password(
+    config: &ServerConfig,
+    new_password: &Zeroizing<String>,
+    reencrypt: bool,
+) -> ErebusResult<()> {
+    let migrations = migrations();
+    let kdf_cost = config.database.kdf_cost();
+    Database::open(&config.database_path(), kdf_cost, &migrations)?.rotate_password(
+        new_password,
+        kdf_cost,
+        reencrypt,
+        &migrations,
+    )
+}
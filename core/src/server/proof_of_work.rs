@@ -0,0 +1,177 @@
+use crate::crypto::proof_of_work::WorkChallenge;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window in which issued challenges are counted to estimate the load.
+const LOAD_WINDOW: Duration = Duration::from_secs(60);
+/// How many challenges per window are considered normal, every doubling beyond that adds
+/// one bit of difficulty.
+const LOAD_THRESHOLD: u32 = 10;
+/// Upper bound for the extra difficulty added under load.
+pub const MAX_LOAD_DIFFICULTY: u8 = 8;
+
+/// Hands out proof-of-work challenges for registrations, harder ones the more are requested.
+pub struct ProofOfWorkGate {
+    base_difficulty: u8,
+    /// Start of the current load window and how many challenges were issued in it.
+    window: Mutex<(Instant, u32)>,
+}
+
+impl ProofOfWorkGate {
+    pub fn new(base_difficulty: u8) -> Self {
+        Self {
+            base_difficulty,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    pub fn issue(&self) -> WorkChallenge {
+        let issued = {
+            let mut window = self.window.lock().unwrap();
+            if window.0.elapsed() > LOAD_WINDOW {
+                *window = (Instant::now(), 0);
+            }
+            window.1 += 1;
+            window.1
+        };
+
+        let load_difficulty = match issued / LOAD_THRESHOLD {
+            0 => 0,
+            load => (load.ilog2() + 1).min(MAX_LOAD_DIFFICULTY as u32) as u8,
+        };
+        let difficulty = self.base_difficulty.saturating_add(load_difficulty);
+        WorkChallenge::generate(difficulty.min(WorkChallenge::MAX_DIFFICULTY))
+    }
+}
+
+/// A request held back until the client proves its work for it. Each connection holds its
+/// own, and a challenge is redeemed at most once, so a solution can neither be replayed nor
+/// used on another connection.
+pub struct PendingWork<T> {
+    pending: Mutex<Option<(WorkChallenge, T)>>,
+}
+
+impl<T> Default for PendingWork<T> {
+    fn default() -> Self {
+        Self {
+            pending: Mutex::new(None),
+        }
+    }
+}
+
+impl<T> PendingWork<T> {
+    /// Holds `request` until `challenge` is solved, replacing what was held before.
+    pub fn hold(&self, challenge: WorkChallenge, request: T) {
+        *self.pending.lock().unwrap() = Some((challenge, request));
+    }
+
+    /// Hands out the held request if `counter` solves its challenge. The challenge is used up
+    /// either way, a client which got it wrong has to ask for a new one.
+    pub fn redeem(&self, counter: u64) -> Option<T> {
+        let (challenge, request) = self.pending.lock().unwrap().take()?;
+        challenge.verify(counter).then_some(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    const BASE_DIFFICULTY: u8 = 4;
+
+    fn gate_with_load(base_difficulty: u8, issued: u32) -> ProofOfWorkGate {
+        let gate = ProofOfWorkGate::new(base_difficulty);
+        *gate.window.lock().unwrap() = (Instant::now(), issued);
+        gate
+    }
+
+    fn solve(challenge: &WorkChallenge) -> u64 {
+        challenge.solve(&AtomicBool::new(false)).unwrap()
+    }
+
+    #[test]
+    fn solved_request_is_handed_out() {
+        let pending = PendingWork::default();
+        let challenge = WorkChallenge::generate(BASE_DIFFICULTY);
+        pending.hold(challenge.clone(), "request");
+
+        assert_eq!(pending.redeem(solve(&challenge)), Some("request"));
+    }
+
+    #[test]
+    fn wrong_counter_uses_up_the_challenge() {
+        let pending = PendingWork::default();
+        let challenge = WorkChallenge::generate(16);
+        let counter = solve(&challenge);
+        pending.hold(challenge, "request");
+
+        assert_eq!(pending.redeem(counter.wrapping_add(1)), None);
+        assert_eq!(pending.redeem(counter), None);
+    }
+
+    #[test]
+    fn replayed_solution_is_rejected() {
+        let pending = PendingWork::default();
+        let challenge = WorkChallenge::generate(BASE_DIFFICULTY);
+        let counter = solve(&challenge);
+        pending.hold(challenge, "request");
+
+        assert_eq!(pending.redeem(counter), Some("request"));
+        assert_eq!(pending.redeem(counter), None);
+    }
+
+    #[test]
+    fn solution_for_another_connection_is_rejected() {
+        let ours = PendingWork::default();
+        let theirs = PendingWork::default();
+        ours.hold(WorkChallenge::generate(16), "ours");
+        let their_challenge = WorkChallenge::generate(16);
+        theirs.hold(their_challenge.clone(), "theirs");
+
+        assert_eq!(ours.redeem(solve(&their_challenge)), None);
+    }
+
+    #[test]
+    fn nothing_pending_is_rejected() {
+        let pending = PendingWork::<()>::default();
+        assert_eq!(pending.redeem(0), None);
+    }
+
+    #[test]
+    fn difficulty_rises_with_load() {
+        let difficulties: Vec<_> = [0, 8, 9, 19, 39, 79]
+            .into_iter()
+            .map(|issued| gate_with_load(BASE_DIFFICULTY, issued).issue().difficulty)
+            .collect();
+
+        // The issued challenge itself counts towards the load
+        assert_eq!(difficulties, [4, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn load_difficulty_is_capped() {
+        let gate = gate_with_load(BASE_DIFFICULTY, u32::MAX / 2);
+        assert_eq!(
+            gate.issue().difficulty,
+            BASE_DIFFICULTY + MAX_LOAD_DIFFICULTY
+        );
+    }
+
+    #[test]
+    fn difficulty_is_clamped_to_what_clients_solve() {
+        let gate = gate_with_load(WorkChallenge::MAX_DIFFICULTY - 1, u32::MAX / 2);
+        assert_eq!(gate.issue().difficulty, WorkChallenge::MAX_DIFFICULTY);
+
+        let gate = gate_with_load(u8::MAX, 0);
+        assert_eq!(gate.issue().difficulty, WorkChallenge::MAX_DIFFICULTY);
+    }
+
+    #[test]
+    fn load_is_forgotten_after_its_window() {
+        let gate = gate_with_load(BASE_DIFFICULTY, 1000);
+        gate.window.lock().unwrap().0 -= LOAD_WINDOW * 2;
+
+        assert_eq!(gate.issue().difficulty, BASE_DIFFICULTY);
+    }
+}
@@ -0,0 +1,209 @@
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many peers the [`IpRateLimiter`] tracks before forgetting the ones which are idle.
+const MAX_TRACKED_PEERS: usize = 10_000;
+/// How often the [`IpRateLimiter`] scans for idle peers at most.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy)]
+pub struct RateLimit {
+    /// How many requests may be made at once.
+    pub burst: u32,
+    /// How long it takes to regain a single request.
+    pub refill_interval: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(burst: u32, refill_interval: Duration) -> Self {
+        Self {
+            burst,
+            refill_interval,
+        }
+    }
+
+    pub fn bucket(&self) -> TokenBucket {
+        TokenBucket {
+            limit: *self,
+            tokens: self.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+}
+
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Takes a token, or returns how long it takes until the next one is available.
+    pub fn try_take(&mut self) -> Result<(), Duration> {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(self.limit.refill_interval.mul_f64(1.0 - self.tokens))
+        }
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.limit.burst as f64
+    }
+
+    fn refill(&mut self) {
+        let refilled =
+            self.updated.elapsed().as_secs_f64() / self.limit.refill_interval.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(self.limit.burst as f64);
+        self.updated = Instant::now();
+    }
+}
+
+/// One token bucket per peer IP, shared by all of its connections.
+pub struct IpRateLimiter {
+    limit: RateLimit,
+    buckets: DashMap<IpAddr, TokenBucket>,
+    last_pruned: Mutex<Instant>,
+}
+
+impl IpRateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: DashMap::new(),
+            last_pruned: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn try_take(&self, ip: IpAddr) -> Result<(), Duration> {
+        if self.buckets.len() >= MAX_TRACKED_PEERS {
+            self.prune();
+        }
+
+        self.buckets
+            .entry(ip)
+            .or_insert_with(|| self.limit.bucket())
+            .try_take()
+    }
+
+    /// Forgets the peers whose buckets are full again. Runs at most once per
+    /// [`PRUNE_INTERVAL`], so a flood of new peers doesn't make every request scan all of them.
+    fn prune(&self) {
+        let Ok(mut last_pruned) = self.last_pruned.try_lock() else {
+            return;
+        };
+        if last_pruned.elapsed() < PRUNE_INTERVAL {
+            return;
+        }
+
+        self.buckets.retain(|_, bucket| !bucket.is_full());
+        *last_pruned = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const LIMIT: RateLimit = RateLimit::new(2, Duration::from_secs(10));
+
+    fn empty_bucket() -> TokenBucket {
+        let mut bucket = LIMIT.bucket();
+        bucket.tokens = 0.0;
+        bucket
+    }
+
+    #[test]
+    fn empty_bucket_rejects_requests() {
+        let mut bucket = LIMIT.bucket();
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_err());
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let mut bucket = empty_bucket();
+        bucket.updated -= LIMIT.refill_interval;
+
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_err());
+    }
+
+    #[test]
+    fn bucket_refills_up_to_its_burst() {
+        let mut bucket = empty_bucket();
+        bucket.updated -= LIMIT.refill_interval * 10;
+
+        assert!(bucket.is_full());
+        assert_eq!(bucket.tokens, LIMIT.burst as f64);
+    }
+
+    #[test]
+    fn retry_after_is_the_time_until_the_next_token() {
+        let mut bucket = empty_bucket();
+        let retry_after = bucket.try_take().unwrap_err();
+        assert!(retry_after <= LIMIT.refill_interval);
+        assert!(retry_after > LIMIT.refill_interval - Duration::from_secs(1));
+
+        let mut bucket = empty_bucket();
+        bucket.updated -= LIMIT.refill_interval / 4;
+        let retry_after = bucket.try_take().unwrap_err();
+        assert!(retry_after <= LIMIT.refill_interval * 3 / 4);
+        assert!(retry_after > LIMIT.refill_interval * 3 / 4 - Duration::from_secs(1));
+    }
+
+    #[test]
+    fn peers_have_separate_buckets() {
+        let limiter = IpRateLimiter::new(LIMIT);
+        let peer = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        assert!(limiter.try_take(peer).is_ok());
+        assert!(limiter.try_take(peer).is_ok());
+        assert!(limiter.try_take(peer).is_err());
+
+        assert!(
+            limiter
+                .try_take(IpAddr::from(Ipv4Addr::new(192, 0, 2, 2)))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn idle_peers_are_evicted_when_too_many_are_tracked() {
+        let limiter = IpRateLimiter::new(LIMIT);
+        let busy_peer = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        limiter.try_take(busy_peer).unwrap();
+        for i in 1..MAX_TRACKED_PEERS as u32 {
+            limiter
+                .buckets
+                .insert(IpAddr::from(Ipv4Addr::from(i)), LIMIT.bucket());
+        }
+        assert_eq!(limiter.buckets.len(), MAX_TRACKED_PEERS);
+
+        // Pruning waits for its interval
+        let new_peer = IpAddr::from(Ipv4Addr::new(198, 51, 100, 1));
+        limiter.try_take(new_peer).unwrap();
+        assert_eq!(limiter.buckets.len(), MAX_TRACKED_PEERS + 1);
+
+        *limiter.last_pruned.lock().unwrap() -= PRUNE_INTERVAL;
+        limiter
+            .try_take(IpAddr::from(Ipv4Addr::new(198, 51, 100, 2)))
+            .unwrap();
+        let mut tracked: Vec<_> = limiter.buckets.iter().map(|entry| *entry.key()).collect();
+        tracked.sort();
+        assert_eq!(
+            tracked,
+            [
+                busy_peer,
+                new_peer,
+                IpAddr::from(Ipv4Addr::new(198, 51, 100, 2))
+            ]
+        );
+    }
+}
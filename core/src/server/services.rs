@@ -1,13 +1,24 @@
+use crate::database::Database;
+use crate::error::ErebusResult;
+
 mod invite_code;
+mod server_identity;
+mod user;
 
 pub struct Services {
+    #[allow(dead_code)]
     invite_code: invite_code::InviteCodeService,
+    identity: server_identity::ServerIdentityService,
+    #[allow(dead_code)]
+    user: user::UserService,
 }
 
 impl Services {
-    pub fn new() -> Self {
-        Self {
+    pub fn initialize(db: &Database) -> ErebusResult<Self> {
+        Ok(Self {
             invite_code: invite_code::InviteCodeService::new(),
-        }
+            identity: server_identity::ServerIdentityService::initialize(db)?,
+            user: user::UserService::initialize(db)?,
+        })
     }
 }
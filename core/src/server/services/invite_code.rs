@@ -21,11 +21,12 @@ impl ErebusServerState {
         self.db.count::<InviteCode>()
     }
 
-    pub fn invite_for_each<F>(&self, f: F) -> ErebusResult<()>
-    where
-        F: Fn(InviteCode) -> ErebusResult<()>,
-    {
-        self.db.for_each::<InviteCode, _>(f)
+    /// The codes of all unused invites.
+    pub fn invite_list(&self) -> ErebusResult<Vec<String>> {
+        self.db
+            .iter::<InviteCode>()?
+            .map(|code| Ok(code?.get_code_string()))
+            .collect()
     }
 
     pub fn invite_find(&self, code: &str) -> ErebusResult<Option<InviteCode>> {
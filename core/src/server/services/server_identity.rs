@@ -0,0 +1,53 @@
+use crate::crypto::identity_proof::IdentityProof;
+use crate::crypto::private_key::PrivateKey;
+use crate::crypto::public_key::PublicKey;
+use crate::database::Database;
+use crate::error::ErebusResult;
+use crate::server::entities::server_identity::ServerIdentity;
+use crate::server::state::ErebusServerState;
+use tracing::info;
+
+pub struct ServerIdentityService {
+    identity: ServerIdentity,
+}
+
+impl ServerIdentityService {
+    pub fn initialize(db: &Database) -> ErebusResult<Self> {
+        let identity = match db.find::<ServerIdentity>(ServerIdentity::ID.to_string())? {
+            Some(identity) => identity,
+            None => {
+                let identity = ServerIdentity::generate();
+                db.save(&identity)?;
+                info!("Generated new server identity");
+                identity
+            }
+        };
+
+        info!("Server identity: {}", identity.public.fingerprint());
+        Ok(Self { identity })
+    }
+}
+
+impl ErebusServerState {
+    pub fn identity_public_key(&self) -> &PublicKey {
+        &self.service.identity.identity.public
+    }
+
+    pub fn identity_fingerprint(&self) -> String {
+        self.identity_public_key().fingerprint()
+    }
+
+    pub fn identity_prove(
+        &self,
+        server_ephemeral: &PrivateKey,
+        client_ephemeral: &PublicKey,
+        protocol_version: u16,
+    ) -> IdentityProof {
+        IdentityProof::create(
+            &self.service.identity.identity.private,
+            server_ephemeral,
+            client_ephemeral,
+            protocol_version,
+        )
+    }
+}
@@ -0,0 +1,126 @@
+use crate::crypto::public_key::PublicKey;
+use crate::database::query::Query;
+use crate::database::Database;
+use crate::error::{ErebusError, ErebusResult};
+use crate::server::entities::invite_code::InviteCode;
+use crate::server::entities::user::{Suspension, User};
+use crate::server::state::ErebusServerState;
+
+pub struct UserService;
+
+impl UserService {
+    pub fn initialize(db: &Database) -> ErebusResult<Self> {
+        db.ensure_indexes::<User>()?;
+        Ok(Self {})
+    }
+}
+
+impl ErebusServerState {
+    pub fn user_register(
+        &self,
+        invite_code: &PublicKey,
+        username: String,
+        public_key: PublicKey,
+    ) -> ErebusResult<User> {
+        if !User::is_valid_username(&username) {
+            return Err(ErebusError::InvalidUsername);
+        }
+
+        // Consuming the invite and creating the user either both happen or neither does.
+        self.db.transaction(|txn| {
+            if txn.take::<InviteCode>(invite_code.as_base64())?.is_none() {
+                return Err(ErebusError::InvalidInviteCode);
+            }
+            if !txn
+                .find_by_index::<User>(&User::USERNAME_INDEX, &username)?
+                .is_empty()
+            {
+                return Err(ErebusError::UsernameTaken);
+            }
+            if txn.find::<User>(public_key.as_base64())?.is_some() {
+                return Err(ErebusError::UserAlreadyExists);
+            }
+
+            let user = User::new(public_key, username);
+            txn.save(&user)?;
+            Ok(user)
+        })
+    }
+
+    pub fn user_find(&self, id: &str) -> ErebusResult<Option<User>> {
+        self.db.find(id.to_string())
+    }
+
+    /// Finds a user by id, failing if the user doesn't exist or is suspended.
+    pub fn user_find_for_login(&self, id: &str) -> ErebusResult<User> {
+        let user = self.user_find(id)?.ok_or(ErebusError::UnknownUser)?;
+        if let Some(suspension) = user.active_suspension() {
+            return Err(ErebusError::UserSuspended {
+                reason: suspension.reason.clone(),
+                expires_at: suspension.expires_at,
+            });
+        }
+        Ok(user)
+    }
+
+    /// Finds a user by id or, failing that, by username.
+    pub fn user_resolve(&self, id_or_username: &str) -> ErebusResult<User> {
+        if let Some(user) = self.user_find(id_or_username)? {
+            return Ok(user);
+        }
+
+        self.user_find_by_username(id_or_username)?
+            .ok_or(ErebusError::UnknownUser)
+    }
+
+    /// Returns up to `limit` users after skipping `offset`, in stored order. The order is stable
+    /// but unrelated to the ids, see [`crate::database::query::Cursor`].
+    pub fn user_list(&self, offset: usize, limit: usize) -> ErebusResult<Vec<User>> {
+        let page = self
+            .db
+            .query(Query::<User>::new().offset(offset).limit(limit))?;
+        Ok(page.items)
+    }
+
+    /// Suspends the user until `expires_at`, or indefinitely. Replaces any earlier suspension.
+    pub fn user_suspend(
+        &self,
+        id: &str,
+        reason: String,
+        expires_at: Option<u64>,
+    ) -> ErebusResult<User> {
+        self.db
+            .update::<User, _>(id.to_string(), |user| {
+                user.suspension = Some(Suspension::new(reason, expires_at));
+                Ok(())
+            })?
+            .ok_or(ErebusError::UnknownUser)
+    }
+
+    pub fn user_unsuspend(&self, id: &str) -> ErebusResult<User> {
+        self.db
+            .update::<User, _>(id.to_string(), |user| {
+                user.suspension = None;
+                Ok(())
+            })?
+            .ok_or(ErebusError::UnknownUser)
+    }
+
+    pub fn user_delete(&self, id: &str) -> ErebusResult<()> {
+        if !self.db.delete::<User>(id.to_string())? {
+            return Err(ErebusError::UnknownUser);
+        }
+        Ok(())
+    }
+
+    pub fn user_find_by_username(&self, username: &str) -> ErebusResult<Option<User>> {
+        let users = self
+            .db
+            .find_by_index::<User>(&User::USERNAME_INDEX, username)?;
+        Ok(users.into_iter().next())
+    }
+
+    pub fn user_count(&self) -> ErebusResult<u64> {
+        self.db.count::<User>()
+    }
+}
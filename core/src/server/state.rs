@@ -1,21 +1,23 @@
 use crate::database::Database;
 use crate::error::ErebusResult;
+use crate::server::config::ServerConfig;
+use crate::server::migrations::migrations;
 use crate::server::services::Services;
-use std::path::PathBuf;
 use tracing::info;
 
 pub struct ErebusServerState {
     pub(crate) db: Database,
-    service: Services,
+    pub(crate) service: Services,
 }
 
 impl ErebusServerState {
-    pub fn new() -> ErebusResult<Self> {
-        let db_path = PathBuf::from("./data/server.db");
-        let db = Database::initialize(&db_path)?;
+    pub fn new(config: &ServerConfig) -> ErebusResult<Self> {
+        std::fs::create_dir_all(&config.data_dir)?;
+        let db_path = config.database_path();
+        let db = Database::initialize(&db_path, config.database.kdf_cost(), &migrations())?;
         info!("Database initialized at: {}", db_path.display());
 
-        let service = Services::new();
+        let service = Services::initialize(&db)?;
         info!("Services initialized");
 
         Ok(Self { db, service })
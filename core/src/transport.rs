@@ -0,0 +1,17 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
+/// Read half of a connection, independent of whether it runs over plain TCP or TLS.
+pub type TransportReader = Box<dyn AsyncRead + Send + Unpin>;
+/// Write half of a connection, independent of whether it runs over plain TCP or TLS.
+pub type TransportWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+pub fn split<S>(stream: S) -> (TransportReader, TransportWriter)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    (Box::new(reader), Box::new(writer))
+}
@@ -0,0 +1,162 @@
+use crate::error::{ErebusError, ErebusResult};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{DigitallySignedStruct, SignatureScheme};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn load_certificates(path: &Path) -> ErebusResult<Vec<CertificateDer<'static>>> {
+    Ok(CertificateDer::pem_file_iter(path)?.collect::<Result<Vec<_>, _>>()?)
+}
+
+#[derive(Clone)]
+pub struct ServerTlsConfig {
+    /// PEM file containing the server certificate chain
+    pub cert_path: PathBuf,
+    /// PEM file containing the private key of the server certificate
+    pub key_path: PathBuf,
+}
+
+impl ServerTlsConfig {
+    pub fn acceptor(&self) -> ErebusResult<TlsAcceptor> {
+        let certs = load_certificates(&self.cert_path)?;
+        let key = PrivateKeyDer::from_pem_file(&self.key_path)?;
+
+        let config = rustls::ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[derive(Clone)]
+pub enum ClientTlsConfig {
+    /// Trust server certificates issued by the root certificate(s) in this PEM file
+    RootCertificate(PathBuf),
+    /// Only accept exactly the server certificate in this PEM file, e.g. a self-signed one
+    PinnedCertificate(PathBuf),
+}
+
+impl ClientTlsConfig {
+    pub fn connector(&self) -> ErebusResult<TlsConnector> {
+        let builder = rustls::ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+
+        let config = match self {
+            Self::RootCertificate(path) => {
+                let mut roots = rustls::RootCertStore::empty();
+                for cert in load_certificates(path)? {
+                    roots.add(cert)?;
+                }
+                builder.with_root_certificates(roots).with_no_client_auth()
+            }
+            Self::PinnedCertificate(path) => {
+                let certificate = load_certificates(path)?
+                    .into_iter()
+                    .next()
+                    .ok_or(ErebusError::TlsCertificateMissing)?;
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(PinnedCertificateVerifier {
+                        certificate,
+                        provider: provider(),
+                    }))
+                    .with_no_client_auth()
+            }
+        };
+
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    pub async fn connect(
+        &self,
+        server_address: &str,
+        stream: TcpStream,
+    ) -> ErebusResult<TlsStream<TcpStream>> {
+        let host = server_address
+            .rsplit_once(':')
+            .map_or(server_address, |(host, _port)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let server_name =
+            ServerName::try_from(host.to_string()).map_err(|_| ErebusError::InvalidServerName)?;
+
+        Ok(self.connector()?.connect(server_name, stream).await?)
+    }
+}
+
+/// Accepts exactly one end-entity certificate, regardless of its issuer or name.
+#[derive(Debug)]
+struct PinnedCertificateVerifier {
+    certificate: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.certificate.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::UnknownIssuer,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
@@ -0,0 +1,130 @@
+use erebus_core::database::entity::Entity;
+use erebus_core::error::{ErebusError, ErebusResult};
+#[cfg(unix)]
+use erebus_core::server::admin::client::AdminClient;
+use erebus_core::server::admin::{ConnectionInfo, UserInfo};
+use erebus_core::server::config::ServerConfig;
+use erebus_core::server::state::ErebusServerState;
+
+/// Talks to the running server over its admin socket, or opens the database directly if the
+/// server is down.
+pub enum Backend {
+    #[cfg(unix)]
+    Server(AdminClient),
+    Database(ErebusServerState),
+}
+
+impl Backend {
+    pub fn open() -> Self {
+        let config = ServerConfig::load().unwrap();
+
+        #[cfg(unix)]
+        if let Some(client) = AdminClient::connect(&config).unwrap() {
+            return Self::Server(client);
+        }
+
+        Self::Database(ErebusServerState::new(&config).unwrap())
+    }
+
+    pub fn invite_generate(&mut self, count: u16) -> ErebusResult<Vec<String>> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.invite_generate(count),
+            Self::Database(state) => (0..count).map(|_| state.invite_generate()).collect(),
+        }
+    }
+
+    pub fn invite_list(&mut self) -> ErebusResult<Vec<String>> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.invite_list(),
+            Self::Database(state) => state.invite_list(),
+        }
+    }
+
+    pub fn identity_fingerprint(&mut self) -> ErebusResult<String> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.identity_fingerprint(),
+            Self::Database(state) => Ok(state.identity_fingerprint()),
+        }
+    }
+
+    /// Returns a page of users and the total number of users.
+    pub fn user_list(&mut self, offset: u32, limit: u32) -> ErebusResult<(Vec<UserInfo>, u64)> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.user_list(offset, limit),
+            Self::Database(state) => {
+                let users = state.user_list(offset as usize, limit as usize)?;
+                Ok((
+                    users.iter().map(UserInfo::from).collect(),
+                    state.user_count()?,
+                ))
+            }
+        }
+    }
+
+    pub fn user_show(&mut self, user: &str) -> ErebusResult<UserInfo> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.user_show(user),
+            Self::Database(state) => Ok((&state.user_resolve(user)?).into()),
+        }
+    }
+
+    pub fn user_suspend(
+        &mut self,
+        user: &str,
+        reason: &str,
+        expires_at: Option<u64>,
+    ) -> ErebusResult<UserInfo> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.user_suspend(user, reason, expires_at),
+            Self::Database(state) => {
+                let user_id = state.user_resolve(user)?.id();
+                Ok((&state.user_suspend(&user_id, reason.to_string(), expires_at)?).into())
+            }
+        }
+    }
+
+    pub fn user_unsuspend(&mut self, user: &str) -> ErebusResult<UserInfo> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.user_unsuspend(user),
+            Self::Database(state) => {
+                let user_id = state.user_resolve(user)?.id();
+                Ok((&state.user_unsuspend(&user_id)?).into())
+            }
+        }
+    }
+
+    pub fn user_delete(&mut self, user: &str) -> ErebusResult<UserInfo> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.user_delete(user),
+            Self::Database(state) => {
+                let user = state.user_resolve(user)?;
+                state.user_delete(&user.id())?;
+                Ok((&user).into())
+            }
+        }
+    }
+
+    pub fn connection_list(&mut self) -> ErebusResult<Vec<ConnectionInfo>> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.connection_list(),
+            Self::Database(_) => Err(ErebusError::ServerNotRunning),
+        }
+    }
+
+    pub fn connection_kick(&mut self, id: &str) -> ErebusResult<bool> {
+        match self {
+            #[cfg(unix)]
+            Self::Server(client) => client.connection_kick(id),
+            Self::Database(_) => Err(ErebusError::ServerNotRunning),
+        }
+    }
+}
@@ -1,16 +1,36 @@
+mod connections;
+mod db;
+mod identity;
 mod invite;
+mod user;
 
 #[derive(Clone, clap::Subcommand)]
 pub enum Command {
+    #[command(subcommand)]
+    /// Commands concerning live connections of the running server
+    Connections(connections::ConnectionsCommand),
+    #[command(subcommand)]
+    /// Commands concerning the database file, run while the server is stopped
+    Db(db::DbCommand),
+    #[command(subcommand)]
+    /// Commands concerning the server identity key
+    Identity(identity::IdentityCommand),
     #[command(subcommand)]
     /// Commands concerning invite codes
     Invite(invite::InviteCommand),
+    #[command(subcommand)]
+    /// Commands concerning user accounts
+    User(user::UserCommand),
 }
 
 impl Command {
     pub fn execute(&self) {
         match self {
+            Self::Connections(command) => command.execute(),
+            Self::Db(command) => command.execute(),
+            Self::Identity(command) => command.execute(),
             Self::Invite(command) => command.execute(),
+            Self::User(command) => command.execute(),
         }
     }
 }
@@ -0,0 +1,19 @@
+mod kick;
+mod list;
+
+#[derive(Clone, clap::Subcommand)]
+pub enum ConnectionsCommand {
+    /// List all live connections
+    List,
+    /// Close a connection by its id
+    Kick { id: String },
+}
+
+impl ConnectionsCommand {
+    pub fn execute(&self) {
+        match self {
+            Self::List => list::handle(),
+            Self::Kick { id } => kick::handle(id),
+        }
+    }
+}
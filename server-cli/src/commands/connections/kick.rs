@@ -0,0 +1,10 @@
+use crate::backend::Backend;
+
+pub fn handle(id: &str) {
+    let mut backend = Backend::open();
+    if backend.connection_kick(id).unwrap() {
+        println!("Kicked connection {id}");
+    } else {
+        println!("There is no connection {id}");
+    }
+}
@@ -0,0 +1,49 @@
+use crate::backend::Backend;
+use comfy_table::Table;
+use erebus_core::formatting::{format_byte_size, format_duration};
+use erebus_core::server::unix_now;
+use std::time::Duration;
+
+pub fn handle() {
+    let mut backend = Backend::open();
+    let connections = backend.connection_list().unwrap();
+    println!("There are {} connections:", connections.len());
+    if connections.is_empty() {
+        return;
+    }
+
+    let now = unix_now();
+    let mut table = Table::new();
+    table.set_header([
+        "ID",
+        "Address",
+        "Connected for",
+        "User",
+        "Protocol",
+        "In",
+        "Out",
+    ]);
+    for connection in connections {
+        let connected_for = Duration::from_secs(now.saturating_sub(connection.connected_since));
+        table.add_row([
+            connection.id,
+            connection.peer_address,
+            format_duration(connected_for),
+            connection.username.unwrap_or_else(|| "-".to_string()),
+            connection
+                .protocol_version
+                .map_or_else(|| "-".to_string(), |version| format!("v{version}")),
+            format!(
+                "{} / {} msgs",
+                format_byte_size(connection.bytes_in as usize),
+                connection.messages_in
+            ),
+            format!(
+                "{} / {} msgs",
+                format_byte_size(connection.bytes_out as usize),
+                connection.messages_out
+            ),
+        ]);
+    }
+    println!("{table}");
+}
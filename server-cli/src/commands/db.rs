@@ -0,0 +1,27 @@
+mod migrate;
+mod rotate_password;
+
+#[derive(Clone, clap::Subcommand)]
+pub enum DbCommand {
+    /// Run the pending schema migrations
+    Migrate {
+        /// Only list the migrations which would run
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Change the database password to NEW_DATABASE_PASSWORD
+    RotatePassword {
+        /// Also replace the data key and re-encrypt every record with it
+        #[arg(long)]
+        reencrypt: bool,
+    },
+}
+
+impl DbCommand {
+    pub fn execute(&self) {
+        match self {
+            Self::Migrate { dry_run } => migrate::handle(*dry_run),
+            Self::RotatePassword { reencrypt } => rotate_password::handle(*reencrypt),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use erebus_core::server::config::ServerConfig;
+use erebus_core::server::migrations;
+
+pub fn handle(dry_run: bool) {
+    let config = ServerConfig::load().unwrap();
+    let plan = if dry_run {
+        migrations::pending(&config).unwrap()
+    } else {
+        migrations::run(&config).unwrap()
+    };
+
+    if plan.format.is_empty() && plan.migrations.is_empty() {
+        println!("The database is up to date");
+        return;
+    }
+
+    for upgrade in &plan.format {
+        println!("format: {}", upgrade.description());
+    }
+    for migration in &plan.migrations {
+        println!(
+            "{}: v{} -> v{}: {}",
+            migration.table, migration.from, migration.to, migration.description
+        );
+    }
+    let verb = if dry_run { "Would run" } else { "Ran" };
+    println!(
+        "{verb} {} format upgrades and {} migrations",
+        plan.format.len(),
+        plan.migrations.len()
+    );
+}
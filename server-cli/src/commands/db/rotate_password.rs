@@ -0,0 +1,17 @@
+use erebus_core::server::config::ServerConfig;
+use erebus_core::server::migrations;
+
+pub fn handle(reencrypt: bool) {
+    let config = ServerConfig::load().unwrap();
+    let new_password = std::env::var("NEW_DATABASE_PASSWORD")
+        .expect("NEW_DATABASE_PASSWORD must be set")
+        .into();
+
+    migrations::rotate_password(&config, &new_password, reencrypt).unwrap();
+    if reencrypt {
+        println!("Changed the database password and re-encrypted every record");
+    } else {
+        println!("Changed the database password");
+    }
+    println!("Set DATABASE_PASSWORD to the new password before starting the server");
+}
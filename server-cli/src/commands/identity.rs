@@ -0,0 +1,15 @@
+mod fingerprint;
+
+#[derive(Clone, clap::Subcommand)]
+pub enum IdentityCommand {
+    /// Print the fingerprint of the server identity key
+    Fingerprint,
+}
+
+impl IdentityCommand {
+    pub fn execute(&self) {
+        match self {
+            Self::Fingerprint => fingerprint::handle(),
+        }
+    }
+}
@@ -0,0 +1,6 @@
+use crate::backend::Backend;
+
+pub fn handle() {
+    let mut backend = Backend::open();
+    println!("{}", backend.identity_fingerprint().unwrap());
+}
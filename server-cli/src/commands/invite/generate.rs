@@ -1,10 +1,9 @@
-use erebus_core::server::state::ErebusServerState;
+use crate::backend::Backend;
 
 pub fn handle(count: u16) {
-    let state = ErebusServerState::new().unwrap();
+    let mut backend = Backend::open();
     println!("Generating {} invite codes...", count);
-    (0..count).for_each(|_| {
-        let code = state.invite_generate().unwrap();
+    for code in backend.invite_generate(count).unwrap() {
         println!("{code}")
-    });
+    }
 }
@@ -1,12 +1,10 @@
-use erebus_core::server::state::ErebusServerState;
+use crate::backend::Backend;
 
 pub fn handle() {
-    let state = ErebusServerState::new().unwrap();
-    println!("There are {} invite codes:", state.invite_count().unwrap());
-    state
-        .invite_for_each(|code| {
-            println!("{}", code.get_code_string());
-            Ok(())
-        })
-        .unwrap();
+    let mut backend = Backend::open();
+    let codes = backend.invite_list().unwrap();
+    println!("There are {} invite codes:", codes.len());
+    for code in codes {
+        println!("{code}");
+    }
 }
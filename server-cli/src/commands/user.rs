@@ -0,0 +1,66 @@
+use erebus_core::formatting::format_duration;
+use erebus_core::server::admin::UserInfo;
+use erebus_core::server::unix_now;
+use std::time::Duration;
+
+mod delete;
+mod list;
+mod show;
+mod suspend;
+mod unsuspend;
+
+#[derive(Clone, clap::Subcommand)]
+pub enum UserCommand {
+    /// List all users, a page at a time
+    List {
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        page: u32,
+        #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+        per_page: u32,
+    },
+    /// Show a user by id or username
+    Show { user: String },
+    /// Suspend a user, dropping their connections
+    Suspend {
+        user: String,
+        #[arg(long)]
+        reason: String,
+        /// Lift the suspension after this long, e.g. 30m, 12h or 7d
+        #[arg(long)]
+        expires_in: Option<String>,
+    },
+    /// Lift the suspension of a user
+    Unsuspend { user: String },
+    /// Delete a user, dropping their connections
+    Delete { user: String },
+}
+
+impl UserCommand {
+    pub fn execute(&self) {
+        match self {
+            Self::List { page, per_page } => list::handle(*page, *per_page),
+            Self::Show { user } => show::handle(user),
+            Self::Suspend {
+                user,
+                reason,
+                expires_in,
+            } => suspend::handle(user, reason, expires_in.as_deref()),
+            Self::Unsuspend { user } => unsuspend::handle(user),
+            Self::Delete { user } => delete::handle(user),
+        }
+    }
+}
+
+fn format_suspension(user: &UserInfo) -> String {
+    match &user.suspension {
+        Some(suspension) if suspension.active => match suspension.expires_at {
+            Some(expires_at) => format!(
+                "suspended for {}: {}",
+                format_duration(Duration::from_secs(expires_at.saturating_sub(unix_now()))),
+                suspension.reason
+            ),
+            None => format!("suspended: {}", suspension.reason),
+        },
+        _ => "active".to_string(),
+    }
+}
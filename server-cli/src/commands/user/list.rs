@@ -0,0 +1,32 @@
+use crate::backend::Backend;
+use crate::commands::user::format_suspension;
+use comfy_table::Table;
+
+pub fn handle(page: u32, per_page: u32) {
+    let Some(offset) = page.saturating_sub(1).checked_mul(per_page) else {
+        eprintln!("Page {page} with {per_page} users per page is out of range");
+        std::process::exit(1);
+    };
+
+    let mut backend = Backend::open();
+    let (users, total) = backend.user_list(offset, per_page).unwrap();
+    let pages = total.div_ceil(per_page as u64).max(1);
+    // The first page exists even without users
+    if page > 1 && offset as u64 >= total {
+        eprintln!("Page {page} is out of range, the last page is {pages}");
+        std::process::exit(1);
+    }
+
+    println!("There are {total} users, showing page {page} of {pages}:");
+    if users.is_empty() {
+        return;
+    }
+
+    let mut table = Table::new();
+    table.set_header(["ID", "Username", "Status"]);
+    for user in users {
+        let status = format_suspension(&user);
+        table.add_row([user.id, user.username, status]);
+    }
+    println!("{table}");
+}
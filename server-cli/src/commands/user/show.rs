@@ -0,0 +1,16 @@
+use crate::backend::Backend;
+use crate::commands::user::format_suspension;
+use erebus_core::formatting::format_duration;
+use erebus_core::server::unix_now;
+use std::time::Duration;
+
+pub fn handle(user: &str) {
+    let mut backend = Backend::open();
+    let user = backend.user_show(user).unwrap();
+    let age = Duration::from_secs(unix_now().saturating_sub(user.created_at));
+
+    println!("ID:       {}", user.id);
+    println!("Username: {}", user.username);
+    println!("Created:  {} ago", format_duration(age));
+    println!("Status:   {}", format_suspension(&user));
+}
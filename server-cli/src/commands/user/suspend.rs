@@ -0,0 +1,31 @@
+use crate::backend::Backend;
+use crate::commands::user::format_suspension;
+use erebus_core::server::unix_now;
+
+pub fn handle(user: &str, reason: &str, expires_in: Option<&str>) {
+    let expires_at = expires_in.map(|expires_in| {
+        let Some(seconds) = parse_duration(expires_in) else {
+            eprintln!("Invalid duration: {expires_in}");
+            std::process::exit(1);
+        };
+        unix_now().saturating_add(seconds)
+    });
+
+    let mut backend = Backend::open();
+    let user = backend.user_suspend(user, reason, expires_at).unwrap();
+    println!("{} is now {}", user.username, format_suspension(&user));
+}
+
+/// Parses durations like `90s`, `30m`, `12h` or `7d` into seconds.
+fn parse_duration(duration: &str) -> Option<u64> {
+    let split = duration.len().checked_sub(1)?;
+    let (amount, unit) = duration.split_at_checked(split)?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    amount.parse::<u64>().ok()?.checked_mul(multiplier)
+}
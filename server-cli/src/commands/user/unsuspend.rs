@@ -0,0 +1,7 @@
+use crate::backend::Backend;
+
+pub fn handle(user: &str) {
+    let mut backend = Backend::open();
+    let user = backend.user_unsuspend(user).unwrap();
+    println!("Lifted the suspension of {}", user.username);
+}
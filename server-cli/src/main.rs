@@ -1,5 +1,6 @@
 use clap::{CommandFactory, Parser};
 
+mod backend;
 mod commands;
 
 #[derive(clap::Parser)]
@@ -1,24 +1,67 @@
+use erebus_core::server::config::ServerConfig;
 use erebus_core::server::ErebusServer;
+use tracing::error;
 
 #[tokio::main]
 async fn main() {
-    init_tracing();
+    let config = match ServerConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    init_tracing(&config);
 
-    let server = ErebusServer::bind("58469").await.unwrap();
-    server.run().await.unwrap();
+    let server = match ErebusServer::bind(&config).await {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to start the server: {e}");
+            std::process::exit(1);
+        }
+    };
+    let result = server.run_until(shutdown_signal()).await;
+    server.shutdown(config.shutdown_grace_period()).await;
+    if let Err(e) = result {
+        error!("Server stopped: {e}");
+        std::process::exit(1);
+    }
 }
 
-fn init_tracing() {
+/// Resolves on SIGINT, or SIGTERM on unix.
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = ctrl_c.await;
+}
+
+fn init_tracing(config: &ServerConfig) {
     use tracing_subscriber::EnvFilter;
 
+    let filter =
+        EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.log_level));
+    let filter = match filter {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Invalid configuration: log_level: {e}");
+            std::process::exit(1);
+        }
+    };
+
     tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-            if cfg!(debug_assertions) {
-                EnvFilter::new("trace")
-            } else {
-                EnvFilter::new("info")
-            }
-        }))
+        .with_env_filter(filter)
         .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
         .init();
 }